@@ -4,6 +4,7 @@ fn main() {
             "protos/footer.proto",
             "protos/header.proto",
             "protos/metadata.proto",
+            "protos/snapshot.proto",
             "protos/timestamp.proto",
         ],
         &["protos/"],
@@ -0,0 +1,431 @@
+//! `.sear` archive reader
+
+mod streaming;
+
+pub use self::streaming::StreamingArchive;
+
+use crate::{
+    builder::{compute_aad, Builder, MAGIC_BYTES},
+    crypto::{
+        kdf,
+        stream::{self, writer::ChunkSize},
+        symmetric,
+    },
+    entry::{attributes::ContentDetection, Entry},
+    error::{Error, ErrorKind},
+    protos::{header::MetadataLocation, Footer, Header, Metadata},
+};
+use anomaly::{ensure, format_err};
+use prost::Message;
+use std::{
+    collections::HashSet,
+    convert::{TryFrom, TryInto},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
+use tai64::TAI64N;
+
+/// Archive reader
+pub struct Archive<R: Read + Seek> {
+    /// Plaintext header of the archive
+    header: Header,
+
+    /// Decrypted metadata describing the archive's contents
+    contents: Contents,
+
+    /// Offset within the plaintext where the contents of entries end
+    entries_end: u64,
+
+    /// Offset within the file where the encrypted stream begins
+    stream_start: u64,
+
+    /// Segments of the encrypted stream with their own nonce prefixes
+    segments: Vec<stream::Segment>,
+
+    /// Encrypted stream reader for the archive's contents
+    reader: stream::Reader<R>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Open an archive, decrypting its index with the given key
+    pub fn open(mut io: R, key: &kdf::Key) -> Result<Self, Error> {
+        let (header, chunk_size) = read_header(&mut io)?;
+        let header_end = io.stream_position()?;
+
+        // Read the `u16` footer length from the very end of the file,
+        // followed by the plaintext footer which precedes it
+        let archive_end = io.seek(SeekFrom::End(-2))?;
+        let mut footer_len = [0u8; 2];
+        io.read_exact(&mut footer_len)?;
+        let footer_len = u64::from(u16::from_le_bytes(footer_len));
+
+        let footer_start = archive_end
+            .checked_sub(footer_len)
+            .filter(|&start| start >= header_end)
+            .ok_or_else(|| format_err!(ErrorKind::Parse, "malformed footer"))?;
+
+        io.seek(SeekFrom::Start(footer_start))?;
+        let mut footer = vec![0u8; footer_len as usize];
+        io.read_exact(&mut footer)?;
+        let footer = Footer::decode(footer.as_slice())?;
+        let segments = stream::Segment::decode_all(footer.segments)?;
+
+        io.seek(SeekFrom::Start(header_end))?;
+
+        let mut reader = stream::Reader::new(
+            io,
+            key.derive_symmetric_key(header.uuid.trim()),
+            compute_aad(),
+            chunk_size,
+            footer_start - header_end,
+        )
+        .with_segments(segments.clone());
+
+        let plaintext_length = reader
+            .plaintext_length()?
+            .expect("ciphertext length should be known");
+
+        let metadata_location = metadata_location(&header)?;
+
+        // The encrypted metadata is located either at the very start of the
+        // stream (preceding entries) or at the very end (following them)
+        let metadata_range = match metadata_location {
+            MetadataLocation::Header => 0..header.metadata_length,
+            MetadataLocation::Footer => {
+                plaintext_length
+                    .checked_sub(footer.metadata_length)
+                    .ok_or_else(|| format_err!(ErrorKind::Parse, "oversized metadata"))?
+                    ..plaintext_length
+            }
+        };
+
+        ensure!(
+            metadata_range.end <= plaintext_length,
+            ErrorKind::Parse,
+            "oversized metadata"
+        );
+
+        // Entries occupy the remainder of the stream
+        let entries_range = match metadata_location {
+            MetadataLocation::Header => metadata_range.end..plaintext_length,
+            MetadataLocation::Footer => 0..metadata_range.start,
+        };
+
+        reader.seek(metadata_range.start)?;
+        let metadata_length = metadata_range.end - metadata_range.start;
+        let mut metadata = Vec::with_capacity(metadata_length as usize);
+        Read::take(&mut reader, metadata_length).read_to_end(&mut metadata)?;
+        let entries_end = entries_range.end;
+        let contents = Contents::decode(&metadata, entries_range)?;
+
+        Ok(Self {
+            header,
+            contents,
+            entries_end,
+            stream_start: header_end,
+            segments,
+            reader,
+        })
+    }
+
+    /// Is the index of entries stored in the header of this archive?
+    pub fn has_index_in_header(&self) -> bool {
+        self.header.metadata_location == MetadataLocation::Header as i32
+    }
+
+    /// Get the UUID which uniquely identifies this archive
+    pub fn uuid(&self) -> &str {
+        self.header.uuid.trim()
+    }
+
+    /// Get the date when this archive was created, if recorded
+    pub fn created_at(&self) -> Option<TAI64N> {
+        self.contents.created_at
+    }
+
+    /// Get the host this archive was created on, if recorded
+    pub fn host(&self) -> Option<&str> {
+        self.contents.host.as_deref()
+    }
+
+    /// Get the name of the user who created this archive, if recorded
+    pub fn username(&self) -> Option<&str> {
+        self.contents.username.as_deref()
+    }
+
+    /// Get the entries contained within this archive
+    pub fn entries(&self) -> &[Entry] {
+        &self.contents.entries
+    }
+
+    /// Obtain a reader for the contents of the entry at the given index
+    pub fn read_entry(&mut self, index: usize) -> Result<io::Take<&mut stream::Reader<R>>, Error> {
+        let (entry, offset) = self
+            .contents
+            .entries
+            .get(index)
+            .zip(self.contents.offsets.get(index))
+            .ok_or_else(|| format_err!(ErrorKind::Argument, "invalid entry index: {}", index))?;
+
+        let length = entry.length;
+        self.reader.seek(*offset)?;
+        Ok(Read::take(&mut self.reader, length))
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Rewrite this archive to the given writer, keeping only the entries
+    /// for which `keep` returns true.
+    ///
+    /// The rewritten archive is encrypted under a fresh UUID (and therefore
+    /// a fresh derived key) with the same chunk size and index placement.
+    /// The metadata of kept entries is preserved as-is, as are the date the
+    /// archive was created and its creator. Contents are streamed from this
+    /// archive to the new one.
+    pub fn rewrite<W: Write>(
+        &mut self,
+        writer: W,
+        key: &kdf::Key,
+        mut keep: impl FnMut(&Entry) -> bool,
+    ) -> Result<(), Error> {
+        let chunk_size = ChunkSize::try_from(self.header.chunk_size)?;
+        let mut builder = Builder::new(writer, key, chunk_size)?;
+
+        builder
+            .content_detection(ContentDetection::Disabled)
+            .host(self.contents.host.clone())
+            .username(self.contents.username.clone());
+
+        if let Some(created_at) = self.contents.created_at {
+            builder.created_at(created_at);
+        }
+
+        let kept: Vec<usize> = (0..self.contents.entries.len())
+            .filter(|&index| keep(&self.contents.entries[index]))
+            .collect();
+
+        if self.has_index_in_header() {
+            let index = kept
+                .iter()
+                .map(|&index| self.contents.entries[index].clone())
+                .collect();
+
+            builder.index_in_header(index)?;
+        }
+
+        for index in kept {
+            let entry = self.contents.entries[index].clone();
+            builder.append(entry, self.read_entry(index)?)?;
+        }
+
+        builder.finish()
+    }
+
+    /// Locate where the contents of this archive's entries end, decrypting
+    /// the partially filled chunk there, so its encrypted stream can be
+    /// resumed to append more entries
+    pub(crate) fn append_point(mut self) -> Result<AppendPoint, Error> {
+        ensure!(
+            !self.has_index_in_header(),
+            ErrorKind::Argument,
+            "can't append to an archive with its index stored in the header"
+        );
+
+        let chunk_size = self.header.chunk_size;
+        let chunk_counter = u32::try_from(self.entries_end / chunk_size)
+            .map_err(|_| format_err!(ErrorKind::Overflow, "STREAM chunk counter overflowed"))?;
+
+        let chunk_start = u64::from(chunk_counter) * chunk_size;
+        self.reader.seek(chunk_start)?;
+
+        let mut plaintext = vec![];
+        Read::take(&mut self.reader, self.entries_end - chunk_start).read_to_end(&mut plaintext)?;
+
+        let offset = self.stream_start
+            + u64::from(chunk_counter) * (chunk_size + symmetric::TAG_SIZE as u64);
+
+        // Segments starting at or after the resumed chunk are rewritten
+        let mut segments = self.segments;
+        segments.retain(|segment| segment.first_chunk < chunk_counter);
+
+        Ok(AppendPoint {
+            header: self.header,
+            created_at: self.contents.created_at,
+            host: self.contents.host,
+            username: self.contents.username,
+            entries: self.contents.entries,
+            segments,
+            chunk_counter,
+            offset,
+            plaintext,
+        })
+    }
+}
+
+/// Position at which the encrypted stream of an existing archive can be
+/// resumed in order to append entries to it
+pub(crate) struct AppendPoint {
+    /// Plaintext header of the archive
+    pub(crate) header: Header,
+
+    /// Date the archive was created
+    pub(crate) created_at: Option<TAI64N>,
+
+    /// Host the archive was built on
+    pub(crate) host: Option<String>,
+
+    /// User who built the archive
+    pub(crate) username: Option<String>,
+
+    /// Entries already within the archive
+    pub(crate) entries: Vec<Entry>,
+
+    /// Segments of the encrypted stream which precede the resumed chunk
+    pub(crate) segments: Vec<stream::Segment>,
+
+    /// Counter of the chunk to resume writing at
+    pub(crate) chunk_counter: u32,
+
+    /// Offset within the file where the resumed chunk begins
+    pub(crate) offset: u64,
+
+    /// Plaintext already stored at the start of the resumed chunk
+    pub(crate) plaintext: Vec<u8>,
+}
+
+/// Decrypted metadata describing the contents of an archive
+#[derive(Clone, Debug)]
+struct Contents {
+    /// Date the archive was created
+    created_at: Option<TAI64N>,
+
+    /// Host the archive was built on
+    host: Option<String>,
+
+    /// User who built the archive
+    username: Option<String>,
+
+    /// Entries within the archive
+    entries: Vec<Entry>,
+
+    /// Offsets of each entry within the plaintext of the encrypted stream
+    offsets: Vec<u64>,
+}
+
+impl Contents {
+    /// Decode serialized metadata, computing the offsets of entries which
+    /// are stored sequentially within the given range of the plaintext
+    fn decode(metadata: &[u8], entries_range: Range<u64>) -> Result<Self, Error> {
+        let metadata = Metadata::decode(metadata)?;
+
+        let created_at = metadata
+            .created_at
+            .as_ref()
+            .map(TryInto::try_into)
+            .transpose()?;
+
+        // Empty strings denote a creator which wasn't recorded
+        let host = Some(metadata.host).filter(|host| !host.is_empty());
+        let username = Some(metadata.username).filter(|username| !username.is_empty());
+
+        let mut entries = vec![];
+        let mut offsets = vec![];
+        let mut offset = entries_range.start;
+
+        for entry in metadata
+            .index
+            .map(|index| index.entries)
+            .unwrap_or_default()
+        {
+            let entry = Entry::try_from(entry)?;
+            offsets.push(offset);
+
+            offset = offset
+                .checked_add(entry.length)
+                .filter(|&end| end <= entries_range.end)
+                .ok_or_else(|| format_err!(ErrorKind::Parse, "entry lengths exceed archive"))?;
+
+            entries.push(entry);
+        }
+
+        Ok(Self {
+            created_at,
+            host,
+            username,
+            entries,
+            offsets,
+        })
+    }
+
+    /// Offset within the plaintext where the last entry ends
+    fn end_offset(&self) -> Option<u64> {
+        let entry = self.entries.last()?;
+        self.offsets.last().map(|offset| offset + entry.length)
+    }
+}
+
+/// Determine which entries are shadowed by a later entry with the same path,
+/// e.g. when a newer version of a file was appended to an archive
+pub fn shadowed(entries: &[Entry]) -> Vec<bool> {
+    let mut seen = HashSet::new();
+    let mut shadowed: Vec<bool> = entries
+        .iter()
+        .rev()
+        .map(|entry| !seen.insert(&entry.path))
+        .collect();
+
+    shadowed.reverse();
+    shadowed
+}
+
+/// Read the magic identifier and plaintext header at the start of an
+/// archive, returning their raw bytes along with whether the archive's index
+/// is stored in the header (so it can be read with a [`StreamingArchive`]).
+///
+/// This allows deciding how to read an archive which can't be seeked (e.g.
+/// from stdin), as long as the returned bytes are read again ahead of the
+/// rest of it.
+pub fn read_head(io: &mut impl Read) -> Result<(Vec<u8>, bool), Error> {
+    let mut head = vec![0u8; MAGIC_BYTES.len() + 2];
+    io.read_exact(&mut head)?;
+
+    let header_len = u16::from_le_bytes([head[head.len() - 2], head[head.len() - 1]]);
+    Read::take(io, header_len.into()).read_to_end(&mut head)?;
+
+    let (header, _) = read_header(&mut head.as_slice())?;
+    let index_in_header = metadata_location(&header)? == MetadataLocation::Header;
+    Ok((head, index_in_header))
+}
+
+/// Read and verify the magic identifier and plaintext header of an archive
+fn read_header(io: &mut impl Read) -> Result<(Header, ChunkSize), Error> {
+    // Read and verify the 6-byte sear archive magic identifier
+    let mut magic = [0u8; 6];
+    io.read_exact(&mut magic)?;
+
+    ensure!(
+        &magic == MAGIC_BYTES,
+        ErrorKind::Parse,
+        "not a sear archive (bad magic bytes)"
+    );
+
+    let header = Header::decode(read_length_prefixed(io)?.as_slice())?;
+    let chunk_size = ChunkSize::try_from(header.chunk_size)?;
+    Ok((header, chunk_size))
+}
+
+/// Get the location of the encrypted metadata described by the header
+fn metadata_location(header: &Header) -> Result<MetadataLocation, Error> {
+    MetadataLocation::from_i32(header.metadata_location)
+        .ok_or_else(|| format_err!(ErrorKind::Parse, "unknown metadata location").into())
+}
+
+/// Read a message prefixed with a `u16` little endian length
+fn read_length_prefixed(io: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut len = [0u8; 2];
+    io.read_exact(&mut len)?;
+
+    let mut msg = vec![0u8; u16::from_le_bytes(len) as usize];
+    io.read_exact(&mut msg)?;
+    Ok(msg)
+}
@@ -0,0 +1,248 @@
+//! Sequential reader for archives which can't be seeked, e.g. pipes
+
+use super::{metadata_location, read_header, Contents};
+use crate::{
+    builder::compute_aad,
+    crypto::{kdf, stream},
+    entry::Entry,
+    error::{Error, ErrorKind},
+    protos::{header::MetadataLocation, Header},
+};
+use anomaly::{ensure, fail, format_err};
+use std::io::{self, Read};
+use tai64::TAI64N;
+
+/// Maximum length of the plaintext footer, including its `u16` length
+const MAX_FOOTER_LENGTH: usize = u16::MAX as usize + 2;
+
+/// Number of bytes to read from the underlying I/O object at a time
+const READ_SIZE: usize = 65536;
+
+/// Archive reader which reads entries in order without seeking.
+///
+/// This requires an archive whose index is stored in the header, ahead of
+/// the entries (see [`Builder::index_in_header`]). Other archives have to be
+/// read with an [`Archive`] instead, e.g. after copying them to a file;
+/// [`read_head`] tells the two apart.
+///
+/// [`Archive`]: crate::Archive
+/// [`Builder::index_in_header`]: crate::Builder::index_in_header
+/// [`read_head`]: super::read_head
+pub struct StreamingArchive<R: Read> {
+    /// Plaintext header of the archive
+    header: Header,
+
+    /// Decrypted metadata describing the archive's contents
+    contents: Contents,
+
+    /// Index of the next entry to be read
+    next: usize,
+
+    /// Encrypted stream reader for the archive's contents
+    reader: stream::Reader<FooterSplitter<R>>,
+}
+
+impl<R: Read> StreamingArchive<R> {
+    /// Open an archive, decrypting its index with the given key
+    pub fn open(mut io: R, key: &kdf::Key) -> Result<Self, Error> {
+        let (header, chunk_size) = read_header(&mut io)?;
+
+        if metadata_location(&header)? != MetadataLocation::Header {
+            fail!(
+                ErrorKind::Argument,
+                "archive index is stored at the end of the archive; \
+                 reading it sequentially requires an index stored in the header"
+            );
+        }
+
+        let mut reader = stream::Reader::new_sequential(
+            FooterSplitter::new(io),
+            key.derive_symmetric_key(header.uuid.trim()),
+            compute_aad(),
+            chunk_size,
+        );
+
+        let mut metadata = Vec::with_capacity(header.metadata_length as usize);
+        Read::take(&mut reader, header.metadata_length).read_to_end(&mut metadata)?;
+
+        ensure!(
+            metadata.len() as u64 == header.metadata_length,
+            ErrorKind::Parse,
+            "truncated metadata"
+        );
+
+        // The total length of the entries isn't known until the end of the
+        // stream is reached, where it's checked by `next_entry`
+        let contents = Contents::decode(&metadata, header.metadata_length..u64::MAX)?;
+
+        Ok(Self {
+            header,
+            contents,
+            next: 0,
+            reader,
+        })
+    }
+
+    /// Get the UUID which uniquely identifies this archive
+    pub fn uuid(&self) -> &str {
+        self.header.uuid.trim()
+    }
+
+    /// Get the date when this archive was created, if recorded
+    pub fn created_at(&self) -> Option<TAI64N> {
+        self.contents.created_at
+    }
+
+    /// Get the host this archive was created on, if recorded
+    pub fn host(&self) -> Option<&str> {
+        self.contents.host.as_deref()
+    }
+
+    /// Get the name of the user who created this archive, if recorded
+    pub fn username(&self) -> Option<&str> {
+        self.contents.username.as_deref()
+    }
+
+    /// Get the entries contained within this archive
+    pub fn entries(&self) -> &[Entry] {
+        &self.contents.entries
+    }
+
+    /// Advance to the next entry in the archive, obtaining a reader for its
+    /// contents. Any unread contents of the previous entry are skipped.
+    ///
+    /// Returns `None` once all entries have been read, after checking that
+    /// the archive ends where its index says it should.
+    pub fn next_entry(&mut self) -> Result<Option<(Entry, impl Read + '_)>, Error> {
+        let entry = match self.contents.entries.get(self.next) {
+            Some(entry) => entry.clone(),
+            None => {
+                let end = self
+                    .contents
+                    .end_offset()
+                    .unwrap_or(self.header.metadata_length);
+
+                self.skip_to(end)?;
+
+                ensure!(
+                    self.reader.read(&mut [0u8])? == 0,
+                    ErrorKind::Parse,
+                    "unexpected data following archive entries"
+                );
+
+                return Ok(None);
+            }
+        };
+
+        self.skip_to(self.contents.offsets[self.next])?;
+        self.next += 1;
+
+        let length = entry.length;
+        Ok(Some((entry, Read::take(&mut self.reader, length))))
+    }
+
+    /// Skip forward to the given offset within the plaintext
+    fn skip_to(&mut self, offset: u64) -> Result<(), Error> {
+        let nbytes = offset
+            .checked_sub(self.reader.position())
+            .ok_or_else(|| format_err!(ErrorKind::Parse, "entries out of order"))?;
+
+        io::copy(&mut Read::take(&mut self.reader, nbytes), &mut io::sink())?;
+
+        ensure!(
+            self.reader.position() == offset,
+            ErrorKind::Parse,
+            "truncated archive"
+        );
+
+        Ok(())
+    }
+}
+
+/// Reader which yields the data preceding the plaintext footer at the end of
+/// an archive, holding back enough data to locate the footer once EOF is hit
+struct FooterSplitter<R> {
+    /// Underlying I/O object to read from
+    io: R,
+
+    /// Data which has been read but not yet yielded
+    buffer: Vec<u8>,
+
+    /// Position within the buffer of the next byte to be yielded
+    pos: usize,
+
+    /// Position within the buffer where the footer starts, once known
+    footer_start: Option<usize>,
+}
+
+impl<R: Read> FooterSplitter<R> {
+    /// Create a new footer splitter
+    fn new(io: R) -> Self {
+        Self {
+            io,
+            buffer: vec![],
+            pos: 0,
+            footer_start: None,
+        }
+    }
+
+    /// Read more data from the underlying I/O object, locating the footer
+    /// if EOF is reached
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+
+        let len = self.buffer.len();
+        self.buffer.resize(len + READ_SIZE, 0);
+
+        let nbytes = loop {
+            match self.io.read(&mut self.buffer[len..]) {
+                Ok(nbytes) => break nbytes,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buffer.truncate(len);
+                    return Err(e);
+                }
+            }
+        };
+
+        self.buffer.truncate(len + nbytes);
+
+        if nbytes == 0 {
+            // The `u16` footer length is located at the very end of the
+            // archive, preceded by the footer itself
+            let footer_start = len
+                .checked_sub(2)
+                .and_then(|footer_end| {
+                    let footer_len =
+                        u16::from_le_bytes([self.buffer[len - 2], self.buffer[len - 1]]);
+                    footer_end.checked_sub(footer_len as usize)
+                })
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed footer"))?;
+
+            self.footer_start = Some(footer_start);
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for FooterSplitter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let available = match self.footer_start {
+                Some(footer_start) => footer_start - self.pos,
+                None => (self.buffer.len() - self.pos).saturating_sub(MAX_FOOTER_LENGTH),
+            };
+
+            if available > 0 || self.footer_start.is_some() || buf.is_empty() {
+                let nbytes = available.min(buf.len());
+                buf[..nbytes].copy_from_slice(&self.buffer[self.pos..self.pos + nbytes]);
+                self.pos += nbytes;
+                return Ok(nbytes);
+            }
+
+            self.fill()?;
+        }
+    }
+}
@@ -32,7 +32,7 @@ pub fn app_config() -> abscissa_core::config::Reader<SearApp> {
 }
 
 /// `sear` application
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct SearApp {
     /// Application configuration.
     config: Option<SearConfig>,
@@ -41,15 +41,6 @@ pub struct SearApp {
     state: application::State<Self>,
 }
 
-impl Default for SearApp {
-    fn default() -> Self {
-        Self {
-            config: None,
-            state: application::State::default(),
-        }
-    }
-}
-
 impl Application for SearApp {
     /// `sear` entrypoint command
     type Cmd = SearCmd;
@@ -90,7 +81,11 @@ impl Application for SearApp {
 
     /// Get tracing configuration from command-line options
     fn tracing_config(&self, command: &SearCmd) -> trace::Config {
-        if command.verbose {
+        // Tracing output goes to stdout, so it's omitted when stdout is
+        // where an archive is being written
+        let archive_to_stdout = command.create && command.archive.as_deref() == Some("-");
+
+        if command.verbose && !archive_to_stdout {
             trace::Config::verbose()
         } else {
             trace::Config::default()
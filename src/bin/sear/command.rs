@@ -1,93 +1,468 @@
 //! `sear`: CLI option parser
 
-use crate::{config::SearConfig, error::Error, op::Op, prelude::*};
-use abscissa_core::{command::Usage, Command, Configurable, Options, Runnable};
-use std::{convert::TryFrom, path::PathBuf, process::exit};
+// TODO(tarcieri): remove this when upgrading to a newer `abscissa_derive`
+#![allow(non_local_definitions)]
+
+use crate::{
+    config::{self, SearConfig},
+    error::Error,
+    op::Op,
+    prelude::*,
+};
+use abscissa_core::{command::Usage, Command, Configurable, FrameworkError, Options, Runnable};
+use gumdrop::{Opt, Parser};
+use std::{
+    convert::TryFrom,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::exit,
+    str::FromStr,
+};
 
 /// sear command line option parser
-#[derive(Command, Debug, Options)]
+#[derive(Clone, Command, Debug, Default)]
 pub struct SearCmd {
-    /// Input/output archive file
-    #[options(short = "f")]
-    pub archive: Option<String>,
+    /// Subcommand given as the first argument instead of tar-style options
+    pub command: Option<SearSubcommand>,
 
-    /// Change to the given directory before archiving
-    #[options(short = "C")]
-    pub chdir: Option<String>,
+    /// Input/output archive file (`-` for stdin/stdout)
+    pub archive: Option<String>,
 
     /// Create a new .sear archive
-    #[options(short = "c")]
     pub create: bool,
 
+    /// Append files to an existing .sear archive
+    pub append: bool,
+
+    /// Append files which are newer than their copies in a .sear archive
+    pub update: bool,
+
+    /// Delete files from a .sear archive
+    pub delete: bool,
+
+    /// Find differences between a .sear archive and the filesystem
+    pub diff: bool,
+
     /// Extract a .sear archive
-    #[options(short = "x")]
     pub extract: bool,
 
+    /// List the contents of a .sear archive
+    pub list: bool,
+
     /// Path to encryption key
-    #[options(short = "K", long = "encryption-key")]
-    pub encryption_key: Option<String>,
+    pub encryption_key: Option<PathBuf>,
 
     /// Path to signing key
-    #[options(short = "S", long = "signing-key")]
-    pub signing_key: Option<String>,
+    pub signing_key: Option<PathBuf>,
 
     /// Path to verify key
-    #[options(short = "V", long = "verify-key")]
-    pub verify_key: Option<String>,
+    pub verify_key: Option<PathBuf>,
+
+    /// Path to configuration file (default: ~/.config/sear/sear.toml)
+    pub config: Option<PathBuf>,
+
+    /// Use the settings of the named profile in the configuration file
+    pub profile: Option<String>,
+
+    /// Size of the chunks of new archives in bytes (1024 or 131072)
+    pub chunk_size: Option<u64>,
+
+    /// Number of threads to encrypt archives with (0 for one per CPU)
+    pub threads: Option<usize>,
+
+    /// Override permissions of added files (octal or symbolic, ala chmod)
+    pub mode: Option<String>,
+
+    /// Detect content types of added files (magic, extension, or none)
+    pub content_type: Option<String>,
+
+    /// Username to record as the archive's creator (default: current user)
+    pub creator_username: Option<String>,
+
+    /// Host to record as where the archive was created (default: hostname)
+    pub creator_host: Option<String>,
+
+    /// Don't record the username or host which created the archive
+    pub no_creator: bool,
+
+    /// Store the index at the start of the archive so it can be streamed
+    pub index_first: bool,
+
+    /// Exclude files matching a gitignore-style pattern (repeatable)
+    pub exclude: Vec<String>,
+
+    /// Exclude files matching the patterns listed in a file (repeatable)
+    pub exclude_from: Vec<String>,
+
+    /// Archive only files changed since the given snapshot, then update it
+    pub listed_incremental: Option<String>,
+
+    /// Name to archive the contents of stdin under (when a file is `-`)
+    pub stdin_name: Option<String>,
+
+    /// List or extract every version of files which were updated (`-u`)
+    pub all_versions: bool,
+
+    /// Format to report differences in (text or json)
+    pub format: Option<String>,
 
     /// Preserve absolute pathnames
-    #[options(short = "P")]
     pub preserve_pathnames: bool,
 
-    /// Preserve file permissions
-    #[options(short = "p")]
+    /// Remove the given number of leading components from paths
+    pub strip_components: Option<usize>,
+
+    /// Rewrite paths with a sed-style expression, e.g. `s,^old/,new/,`
+    pub transform: Vec<String>,
+
+    /// Preserve file permissions (including setuid/setgid) when extracting
     pub preserve_permissions: bool,
 
+    /// Restore file ownership when extracting (default for superuser)
+    pub same_owner: bool,
+
+    /// Extract files as yourself (default for ordinary users)
+    pub no_same_owner: bool,
+
+    /// Always use numbers for user/group names when extracting
+    pub numeric_owner: bool,
+
+    /// Restore SELinux labels stored in the archive when extracting
+    pub selinux: bool,
+
+    /// Relabel extracted files using the system's default SELinux contexts
+    pub selinux_relabel: bool,
+
+    /// Names read with -T are separated by NUL bytes rather than newlines
+    pub null: bool,
+
     /// Verbose mode
-    #[options(short = "v")]
     pub verbose: bool,
 
-    /// Files to include in the archive
+    /// Files, file lists (`-T`), and directories (`-C`) in the order given
+    pub positionals: Vec<Positional>,
+}
+
+/// Subcommands of `sear`, which take their own options
+#[derive(Clone, Debug, Options)]
+pub enum SearSubcommand {
+    /// Compare the indexes of two archives
+    Diff(DiffCmd),
+}
+
+/// Usage: sear diff [OPTIONS] OLD.sear NEW.sear [FILES...]
+#[derive(Clone, Debug, Default, Options)]
+pub struct DiffCmd {
+    /// Path to encryption key
+    #[options(short = "K", long = "encryption-key")]
+    pub encryption_key: Option<PathBuf>,
+
+    /// Path to encryption key for the new archive (default: -K)
+    #[options(no_short, long = "new-key", meta = "KEY")]
+    pub new_key: Option<PathBuf>,
+
+    /// Path to configuration file (default: ~/.config/sear/sear.toml)
+    #[options(no_short, long = "config", meta = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Use the settings of the named profile in the configuration file
+    #[options(no_short, long = "profile", meta = "NAME")]
+    pub profile: Option<String>,
+
+    /// Format to report differences in (text or json)
+    #[options(no_short, long = "format", meta = "FORMAT")]
+    pub format: Option<String>,
+
+    /// Old and new archives, followed by the files to compare (default: all)
     #[options(free)]
-    pub files: Vec<String>,
+    pub args: Vec<String>,
 }
 
-impl Default for SearCmd {
-    fn default() -> Self {
-        Self {
-            archive: None,
-            chdir: None,
-            create: false,
-            extract: false,
-            encryption_key: None,
-            signing_key: None,
-            verify_key: None,
-            preserve_pathnames: false,
-            preserve_permissions: false,
-            verbose: false,
-            files: vec![],
+/// Arguments whose order on the command line matters, e.g. files following
+/// a `-C` option are relative to its directory
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Positional {
+    /// Directory given with `-C`
+    Chdir(String),
+
+    /// File given as a free argument
+    File(String),
+
+    /// File list given with `-T`
+    List(String),
+}
+
+impl SearCmd {
+    /// Fill in options which weren't given on the command line with the
+    /// settings of the selected profile in the configuration file
+    fn with_config(&self, config: &SearConfig) -> Result<Self, Error> {
+        let mut args = self.clone();
+
+        if let Some(SearSubcommand::Diff(diff)) = &mut args.command {
+            let profile = config.profile(diff.profile.as_deref())?;
+            diff.encryption_key = diff.encryption_key.take().or(profile.encryption_key);
+            return Ok(args);
+        }
+
+        let profile = config.profile(self.profile.as_deref())?;
+
+        args.encryption_key = args.encryption_key.or(profile.encryption_key);
+        args.signing_key = args.signing_key.or(profile.signing_key);
+        args.verify_key = args.verify_key.or(profile.verify_key);
+        args.chunk_size = args.chunk_size.or(profile.chunk_size);
+        args.threads = args.threads.or(profile.threads);
+        args.content_type = args.content_type.or(profile.content_type);
+
+        // Patterns given on the command line come last so they can negate
+        // those in the configuration file
+        args.exclude = profile.exclude.into_iter().chain(args.exclude).collect();
+
+        Ok(args)
+    }
+}
+
+/// Usage text in the format `#[derive(Options)]` generates
+const USAGE: &str = "\
+Positional arguments:
+  files                       Files to include in the archive
+
+Optional arguments:
+  -f, --archive ARCHIVE       Input/output archive file (`-` for stdin/stdout)
+  -C, --chdir DIR             Change to the given directory for the files which follow (repeatable)
+  -c, --create                Create a new .sear archive
+  -r, --append                Append files to an existing .sear archive
+  -u, --update                Append files which are newer than their copies in a .sear archive
+  --delete                    Delete files from a .sear archive
+  -d, --diff                  Find differences between a .sear archive and the filesystem
+  -x, --extract               Extract a .sear archive
+  -t, --list                  List the contents of a .sear archive
+  -K, --encryption-key ENCRYPTION-KEY
+                              Path to encryption key
+  -S, --signing-key SIGNING-KEY
+                              Path to signing key
+  -V, --verify-key VERIFY-KEY Path to verify key
+  --config FILE               Path to configuration file (default: ~/.config/sear/sear.toml)
+  --profile NAME              Use the settings of the named profile in the configuration file
+  --chunk-size BYTES          Size of the chunks of new archives in bytes (1024 or 131072)
+  --threads N                 Number of threads to encrypt archives with (0 for one per CPU)
+  --mode CHANGES              Override permissions of added files (octal or symbolic, ala chmod)
+  --content-type STRATEGY     Detect content types of added files (magic, extension, or none)
+  --creator-username NAME     Username to record as the archive's creator (default: current user)
+  --creator-host HOST         Host to record as where the archive was created (default: hostname)
+  --no-creator                Don't record the username or host which created the archive
+  --index-first               Store the index at the start of the archive so it can be streamed
+  --exclude PATTERN           Exclude files matching a gitignore-style pattern (repeatable)
+  --exclude-from FILE         Exclude files matching the patterns listed in a file (repeatable)
+  -g, --listed-incremental SNAPSHOT
+                              Archive only files changed since the given snapshot, then update it
+  --stdin-name NAME           Name to archive the contents of stdin under (when a file is `-`)
+  --all-versions              List or extract every version of files which were updated (`-u`)
+  --format FORMAT             Format to report differences in (text or json)
+  -P, --preserve-pathnames    Preserve absolute pathnames
+  --strip-components N        Remove the given number of leading components from paths
+  --transform EXPR            Rewrite paths with a sed-style expression, e.g. `s,^old/,new/,`
+  -p, --preserve-permissions  Preserve file permissions (including setuid/setgid) when extracting
+  --same-owner                Restore file ownership when extracting (default for superuser)
+  --no-same-owner             Extract files as yourself (default for ordinary users)
+  --numeric-owner             Always use numbers for user/group names when extracting
+  --selinux                   Restore SELinux labels stored in the archive when extracting
+  --selinux-relabel           Relabel extracted files using the system's default SELinux contexts
+  -T, --files-from FILE       Read names of files from the given file (`-` for stdin, repeatable)
+  --null                      Names read with -T are separated by NUL bytes rather than newlines
+  -v, --verbose               Verbose mode";
+
+impl Options for SearCmd {
+    /// Parse options by hand, rather than with `#[derive(Options)]`, so the
+    /// order of `-C`, `-T`, and file arguments is recorded as they're parsed
+    fn parse<S: AsRef<str>>(parser: &mut Parser<'_, S>) -> Result<Self, gumdrop::Error> {
+        // Subcommands are only recognized as the first argument, so files
+        // with the same names can still be given following other options
+        if let Some(Opt::Free(name)) = parser.clone().next_opt() {
+            if SearSubcommand::command_usage(name).is_some() {
+                parser.next_opt();
+
+                return Ok(Self {
+                    command: Some(SearSubcommand::parse_command(name, parser)?),
+                    ..Self::default()
+                });
+            }
         }
+
+        let mut cmd = Self::default();
+
+        while let Some(opt) = parser.next_opt() {
+            let (name, arg) = match opt {
+                Opt::Free(file) => {
+                    cmd.positionals.push(Positional::File(file.to_owned()));
+                    continue;
+                }
+                Opt::Short(short) => match long_name(short) {
+                    Some(name) => (name, None),
+                    None => return Err(gumdrop::Error::unrecognized_short(short)),
+                },
+                Opt::Long(name) => (name, None),
+                Opt::LongWithArg(name, arg) => (name, Some(arg)),
+            };
+
+            let flag = || match arg {
+                Some(_) => Err(gumdrop::Error::unexpected_argument(opt)),
+                None => Ok(true),
+            };
+
+            let mut value = || {
+                arg.or_else(|| parser.next_arg())
+                    .map(str::to_owned)
+                    .ok_or_else(|| gumdrop::Error::missing_argument(opt))
+            };
+
+            match name {
+                "archive" => cmd.archive = Some(value()?),
+                "chdir" => cmd.positionals.push(Positional::Chdir(value()?)),
+                "create" => cmd.create = flag()?,
+                "append" => cmd.append = flag()?,
+                "update" => cmd.update = flag()?,
+                "delete" => cmd.delete = flag()?,
+                "diff" => cmd.diff = flag()?,
+                "extract" => cmd.extract = flag()?,
+                "list" => cmd.list = flag()?,
+                "encryption-key" => cmd.encryption_key = Some(value()?.into()),
+                "signing-key" => cmd.signing_key = Some(value()?.into()),
+                "verify-key" => cmd.verify_key = Some(value()?.into()),
+                "config" => cmd.config = Some(value()?.into()),
+                "profile" => cmd.profile = Some(value()?),
+                "chunk-size" => cmd.chunk_size = Some(parse_value(opt, &value()?)?),
+                "threads" => cmd.threads = Some(parse_value(opt, &value()?)?),
+                "mode" => cmd.mode = Some(value()?),
+                "content-type" => cmd.content_type = Some(value()?),
+                "creator-username" => cmd.creator_username = Some(value()?),
+                "creator-host" => cmd.creator_host = Some(value()?),
+                "no-creator" => cmd.no_creator = flag()?,
+                "index-first" => cmd.index_first = flag()?,
+                "exclude" => cmd.exclude.push(value()?),
+                "exclude-from" => cmd.exclude_from.push(value()?),
+                "listed-incremental" => cmd.listed_incremental = Some(value()?),
+                "stdin-name" => cmd.stdin_name = Some(value()?),
+                "all-versions" => cmd.all_versions = flag()?,
+                "format" => cmd.format = Some(value()?),
+                "preserve-pathnames" => cmd.preserve_pathnames = flag()?,
+                "strip-components" => cmd.strip_components = Some(parse_value(opt, &value()?)?),
+                "transform" => cmd.transform.push(value()?),
+                "preserve-permissions" => cmd.preserve_permissions = flag()?,
+                "same-owner" => cmd.same_owner = flag()?,
+                "no-same-owner" => cmd.no_same_owner = flag()?,
+                "numeric-owner" => cmd.numeric_owner = flag()?,
+                "selinux" => cmd.selinux = flag()?,
+                "selinux-relabel" => cmd.selinux_relabel = flag()?,
+                "files-from" => cmd.positionals.push(Positional::List(value()?)),
+                "null" => cmd.null = flag()?,
+                "verbose" => cmd.verbose = flag()?,
+                _ => return Err(gumdrop::Error::unrecognized_option(opt)),
+            }
+        }
+
+        Ok(cmd)
     }
+
+    fn parse_command<S: AsRef<str>>(
+        name: &str,
+        _parser: &mut Parser<'_, S>,
+    ) -> Result<Self, gumdrop::Error> {
+        Err(gumdrop::Error::unrecognized_command(name))
+    }
+
+    fn usage() -> &'static str {
+        USAGE
+    }
+
+    fn command_usage(command: &str) -> Option<&'static str> {
+        SearSubcommand::command_usage(command)
+    }
+
+    fn command_list() -> Option<&'static str> {
+        Some(SearSubcommand::usage())
+    }
+}
+
+/// Get the long name of the option with the given short name
+fn long_name(short: char) -> Option<&'static str> {
+    Some(match short {
+        'f' => "archive",
+        'C' => "chdir",
+        'c' => "create",
+        'r' => "append",
+        'u' => "update",
+        'd' => "diff",
+        'x' => "extract",
+        't' => "list",
+        'K' => "encryption-key",
+        'S' => "signing-key",
+        'V' => "verify-key",
+        'g' => "listed-incremental",
+        'P' => "preserve-pathnames",
+        'p' => "preserve-permissions",
+        'T' => "files-from",
+        'v' => "verbose",
+        _ => return None,
+    })
+}
+
+/// Parse the argument of an option
+fn parse_value<T>(opt: Opt<'_>, arg: &str) -> Result<T, gumdrop::Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    arg.parse()
+        .map_err(|err: T::Err| gumdrop::Error::failed_parse(opt, err.to_string()))
 }
 
 impl Configurable<SearConfig> for SearCmd {
+    /// Use the configuration file given with `--config`, or the default one
+    /// if it exists
     fn config_path(&self) -> Option<PathBuf> {
-        // TODO(tarcieri): configuration support
-        None
+        let config = match &self.command {
+            Some(SearSubcommand::Diff(diff)) => &diff.config,
+            None => &self.config,
+        };
+
+        match config {
+            Some(path) => Some(path.clone()),
+            None => config::default_path().filter(|path| path.is_file()),
+        }
+    }
+
+    /// Resolve key paths relative to the directory containing the
+    /// configuration file, so configurations can be shared along with keys
+    fn process_config(&self, mut config: SearConfig) -> Result<SearConfig, FrameworkError> {
+        if let Some(dir) = self.config_path().as_deref().and_then(Path::parent) {
+            config.resolve_paths(dir);
+        }
+
+        Ok(config)
     }
 }
 
 impl Runnable for SearCmd {
     fn run(&self) {
-        Op::try_from(self).unwrap_or_else(print_error_message).run();
+        let op = self
+            .with_config(&app_config())
+            .and_then(|args| Op::try_from(&args));
+
+        op.unwrap_or_else(|err| print_error_message(self, err))
+            .run();
     }
 }
 
-/// Print an error message
-fn print_error_message(err: Error) -> Op {
+/// Print an error message along with the usage of the given command
+fn print_error_message(cmd: &SearCmd, err: Error) -> Op {
     status_err!("{}", err);
-    Usage::for_command::<SearCmd>()
-        .print_subcommand(&[])
-        .unwrap();
+
+    match cmd.command.as_ref().and_then(Options::command_name) {
+        Some(name) => eprintln!("\n{}", SearCmd::command_usage(name).unwrap_or_default()),
+        None => Usage::for_command::<SearCmd>()
+            .print_subcommand(&[])
+            .unwrap(),
+    }
+
     exit(1);
 }
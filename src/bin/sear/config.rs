@@ -1,8 +1,164 @@
 //! Sear Config
 
+use crate::{
+    error::{Error, ErrorKind},
+    prelude::*,
+};
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
 
-/// Sear Configuration
+/// Sear Configuration, e.g.
+///
+/// ```toml
+/// [defaults]
+/// encryption_key = "keys/default.key"
+/// exclude = ["target/"]
+///
+/// [profiles.databases]
+/// encryption_key = "keys/databases.key"
+/// chunk_size = 1024
+/// ```
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
-pub struct SearConfig {}
+pub struct SearConfig {
+    /// Settings used unless overridden by a profile or the command line
+    #[serde(default)]
+    pub defaults: Profile,
+
+    /// Named sets of settings, selected with `--profile`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl SearConfig {
+    /// Get the settings of the given profile, combined with the defaults
+    /// (or just the defaults if no profile is given)
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, Error> {
+        self.check_unsupported()?;
+
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(self.defaults.clone()),
+        };
+
+        let profile = self
+            .profiles
+            .get(name)
+            .ok_or_else(|| format_err!(ErrorKind::Config, "no such profile: `{}`", name))?;
+
+        Ok(self.defaults.clone().merge(profile))
+    }
+
+    /// Reject settings which are recognized but not supported yet, rather
+    /// than silently ignoring them
+    fn check_unsupported(&self) -> Result<(), Error> {
+        for profile in Some(&self.defaults)
+            .into_iter()
+            .chain(self.profiles.values())
+        {
+            ensure!(
+                profile.recipients.is_none(),
+                ErrorKind::Config,
+                "`recipients` isn't supported yet (archives are encrypted with `encryption_key`)"
+            );
+
+            ensure!(
+                profile.compression.is_none(),
+                ErrorKind::Config,
+                "`compression` isn't supported yet (archives are stored uncompressed)"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Resolve relative key paths against the given directory, i.e. the one
+    /// containing the configuration file
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        for profile in Some(&mut self.defaults)
+            .into_iter()
+            .chain(self.profiles.values_mut())
+        {
+            for key_path in [
+                &mut profile.encryption_key,
+                &mut profile.signing_key,
+                &mut profile.verify_key,
+            ]
+            .iter_mut()
+            .filter_map(|key_path| key_path.as_mut())
+            {
+                *key_path = dir.join(&key_path);
+            }
+        }
+    }
+}
+
+/// Settings in a configuration file, which provide defaults for the
+/// corresponding command-line options
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Path to encryption key
+    pub encryption_key: Option<PathBuf>,
+
+    /// Path to signing key
+    pub signing_key: Option<PathBuf>,
+
+    /// Path to verify key
+    pub verify_key: Option<PathBuf>,
+
+    /// Size of the chunks of new archives in bytes (1024 or 131072)
+    pub chunk_size: Option<u64>,
+
+    /// Number of threads to encrypt archives with (0 for one per CPU)
+    pub threads: Option<usize>,
+
+    /// How to detect the content types of added files
+    pub content_type: Option<String>,
+
+    /// Gitignore-style patterns for files to exclude from archives
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Recipients to encrypt archives to (not supported yet)
+    pub recipients: Option<Vec<String>>,
+
+    /// Compression algorithm for new archives (not supported yet)
+    pub compression: Option<String>,
+}
+
+impl Profile {
+    /// Combine these settings with those of another profile, which take
+    /// precedence. Exclude patterns from both are used.
+    fn merge(self, other: &Profile) -> Self {
+        Self {
+            encryption_key: other.encryption_key.clone().or(self.encryption_key),
+            signing_key: other.signing_key.clone().or(self.signing_key),
+            verify_key: other.verify_key.clone().or(self.verify_key),
+            chunk_size: other.chunk_size.or(self.chunk_size),
+            threads: other.threads.or(self.threads),
+            content_type: other.content_type.clone().or(self.content_type),
+            exclude: self
+                .exclude
+                .into_iter()
+                .chain(other.exclude.iter().cloned())
+                .collect(),
+            recipients: other.recipients.clone().or(self.recipients),
+            compression: other.compression.clone().or(self.compression),
+        }
+    }
+}
+
+/// Get the path of the default configuration file:
+/// `~/.config/sear/sear.toml` (or under `$XDG_CONFIG_HOME` if set)
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    Some(config_dir.join("sear").join("sear.toml"))
+}
@@ -16,7 +16,6 @@ pub enum ErrorKind {
     Argument,
 
     /// Error in configuration file
-    #[allow(dead_code)]
     #[error("config error")]
     Config,
 
@@ -2,22 +2,46 @@
 
 mod chdir;
 mod create;
+mod delete;
+mod diff;
+mod extract;
+mod list;
 
-use self::create::CreateOp;
+use self::{
+    chdir::Chdir, create::CreateOp, delete::DeleteOp, diff::DiffOp, extract::ExtractOp,
+    list::ListOp,
+};
 use crate::{
-    command::SearCmd,
+    command::{Positional, SearCmd, SearSubcommand},
     error::{Error, ErrorKind},
     prelude::*,
 };
 use abscissa_core::Runnable;
+use sear::{archive, crypto::kdf, transform::Transform, Archive, StreamingArchive};
 use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 /// Operations on `.sear` files parsed from command-line arguments
 #[derive(Debug)]
 pub enum Op {
-    /// Create a new `.sear` file
+    /// Create a new `.sear` file (or append/update an existing one)
     Create(CreateOp),
+
+    /// Delete entries from an existing `.sear` file
+    Delete(DeleteOp),
+
+    /// Compare an existing `.sear` file against the filesystem or another one
+    Diff(DiffOp),
+
+    /// Extract an existing `.sear` file
+    Extract(ExtractOp),
+
+    /// List the contents of an existing `.sear` file
+    List(ListOp),
 }
 
 impl TryFrom<&SearCmd> for Op {
@@ -25,15 +49,46 @@ impl TryFrom<&SearCmd> for Op {
 
     /// Parse command-line arguments into the appropriate operation
     fn try_from(cmd: &SearCmd) -> Result<Self, Error> {
-        if cmd.create && cmd.extract {
-            fail!(ErrorKind::Argument, "-c and -x are orthogonal (pick one)");
+        if let Some(SearSubcommand::Diff(diff_cmd)) = &cmd.command {
+            return Ok(Op::Diff(DiffOp::for_archives(diff_cmd)?));
         }
 
-        if !cmd.create {
-            fail!(ErrorKind::Argument, "neither -c nor -x specified");
+        let num_ops = [
+            cmd.create,
+            cmd.append,
+            cmd.update,
+            cmd.delete,
+            cmd.diff,
+            cmd.extract,
+            cmd.list,
+        ]
+        .iter()
+        .filter(|&&op| op)
+        .count();
+
+        if num_ops > 1 {
+            fail!(
+                ErrorKind::Argument,
+                "-c, -d, -r, -t, -u, -x, and --delete are orthogonal (pick one)"
+            );
         }
 
-        Ok(Op::Create(CreateOp::new(cmd)?))
+        if cmd.create || cmd.append || cmd.update {
+            Ok(Op::Create(CreateOp::new(cmd)?))
+        } else if cmd.delete {
+            Ok(Op::Delete(DeleteOp::new(cmd)?))
+        } else if cmd.diff {
+            Ok(Op::Diff(DiffOp::new(cmd)?))
+        } else if cmd.extract {
+            Ok(Op::Extract(ExtractOp::new(cmd)?))
+        } else if cmd.list {
+            Ok(Op::List(ListOp::new(cmd)?))
+        } else {
+            fail!(
+                ErrorKind::Argument,
+                "neither -c, -d, -r, -t, -u, -x, nor --delete specified"
+            );
+        }
     }
 }
 
@@ -41,6 +96,10 @@ impl Runnable for Op {
     fn run(&self) {
         let result = match self {
             Op::Create(create_op) => create_op.perform(),
+            Op::Delete(delete_op) => delete_op.perform(),
+            Op::Diff(diff_op) => diff_op.perform(),
+            Op::Extract(extract_op) => extract_op.perform(),
+            Op::List(list_op) => list_op.perform(),
         };
 
         if let Err(e) = result {
@@ -49,3 +108,196 @@ impl Runnable for Op {
         }
     }
 }
+
+/// Get the files given on the command line: the positional arguments, and
+/// the names listed in any files given with `-T` (separated by newlines, or
+/// NUL bytes with `--null`)
+fn file_args(args: &SearCmd) -> Result<Vec<PathBuf>, Error> {
+    Ok(chdir_file_args(args)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Get the files given on the command line along with the directories
+/// they're relative to, i.e. those given by the `-C` options preceding them
+fn chdir_file_args(args: &SearCmd) -> Result<Vec<(Chdir, PathBuf)>, Error> {
+    let separator = if args.null { b'\0' } else { b'\n' };
+
+    let lists_from_stdin = args
+        .positionals
+        .iter()
+        .filter(|arg| matches!(arg, Positional::List(list) if is_stdio(Path::new(list))))
+        .count();
+
+    if lists_from_stdin > 0 {
+        let reads_archive = !(args.create || args.append || args.update);
+
+        ensure!(
+            lists_from_stdin == 1,
+            ErrorKind::Argument,
+            "only one file list can be read from stdin (`-T -`)"
+        );
+
+        ensure!(
+            !(reads_archive && args.archive.as_deref() == Some("-")),
+            ErrorKind::Argument,
+            "can't read both the archive (`-f -`) and a file list (`-T -`) from stdin"
+        );
+
+        ensure!(
+            !args
+                .positionals
+                .iter()
+                .any(|arg| matches!(arg, Positional::File(file) if is_stdio(Path::new(file)))),
+            ErrorKind::Argument,
+            "can't read both a file (`-`) and a file list (`-T -`) from stdin"
+        );
+    }
+
+    let mut chdir = Chdir::default();
+    let mut files = vec![];
+
+    for arg in &args.positionals {
+        match arg {
+            Positional::Chdir(dir) => chdir = chdir.join(dir),
+            Positional::File(file) => files.push((chdir.clone(), PathBuf::from(file))),
+            Positional::List(list) => {
+                let bytes = if is_stdio(Path::new(list)) {
+                    let mut bytes = vec![];
+                    io::stdin().lock().read_to_end(&mut bytes)?;
+                    bytes
+                } else {
+                    fs::read(list)?
+                };
+
+                for name in bytes.split(|&byte| byte == separator) {
+                    if !name.is_empty() {
+                        files.push((chdir.clone(), path_from_bytes(name.to_vec())?));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Get the directory given with `-C` for operations which only support one
+fn chdir_arg(args: &SearCmd) -> Result<Chdir, Error> {
+    let mut dirs = args.positionals.iter().filter_map(|arg| match arg {
+        Positional::Chdir(dir) => Some(dir),
+        _ => None,
+    });
+
+    match (dirs.next(), dirs.next()) {
+        (None, _) => Ok(Chdir::default()),
+        (Some(dir), None) => Ok(Chdir::default().join(dir)),
+        _ => fail!(
+            ErrorKind::Argument,
+            "multiple -C options can only be used when creating an archive"
+        ),
+    }
+}
+
+/// Get the transform of entry paths given on the command line with
+/// `--strip-components` and `--transform`
+fn transform_args(args: &SearCmd) -> Result<Transform, Error> {
+    let mut transform = Transform::new();
+    transform
+        .strip_components(args.strip_components.unwrap_or(0))
+        .preserve_absolute(args.preserve_pathnames);
+
+    for expression in &args.transform {
+        transform.add_rule(expression)?;
+    }
+
+    Ok(transform)
+}
+
+/// Convert a filename read from a file list into a path
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, Error> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+    Ok(OsString::from_vec(bytes).into())
+}
+
+/// Convert a filename read from a file list into a path
+#[cfg(windows)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, Error> {
+    String::from_utf8(bytes)
+        .map(Into::into)
+        .map_err(|_| format_err!(ErrorKind::Argument, "invalid UTF-8 in file list").into())
+}
+
+/// Reader for an archive on stdin, preceded by its header (which has already
+/// been read from stdin)
+type StdinReader = io::Chain<io::Cursor<Vec<u8>>, io::Stdin>;
+
+/// Archive being read from a file or stdin
+enum InputArchive {
+    /// Archive in a file (or spooled from stdin), whose entries can be read
+    /// in any order
+    File(Archive<File>),
+
+    /// Archive on stdin with its index in the header, whose entries are read
+    /// in order as they're streamed in
+    Stream(StreamingArchive<StdinReader>),
+}
+
+/// Open the archive at the given path (or `-` for stdin).
+///
+/// Archives on stdin are streamed if their index is stored in the header.
+/// Otherwise the index is at the end, so they're spooled to a temporary file
+/// and read from there.
+fn open_archive(path: &Path, key: &kdf::Key) -> Result<InputArchive, Error> {
+    if !is_stdio(path) {
+        return Ok(InputArchive::File(Archive::open(File::open(path)?, key)?));
+    }
+
+    let mut stdin = io::stdin();
+    let (head, index_in_header) = archive::read_head(&mut stdin)?;
+    let mut input = io::Cursor::new(head).chain(stdin);
+
+    if index_in_header {
+        return Ok(InputArchive::Stream(StreamingArchive::open(input, key)?));
+    }
+
+    let mut spool = tempfile::tempfile()?;
+    io::copy(&mut input, &mut spool)?;
+    spool.seek(SeekFrom::Start(0))?;
+    Ok(InputArchive::File(Archive::open(spool, key)?))
+}
+
+/// Path to a temporary file in the same directory as the given archive
+fn temp_path(archive: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(archive.file_name().unwrap_or_default());
+    file_name.push(".tmp");
+    archive.with_file_name(file_name)
+}
+
+/// Replace an archive with the rewritten copy at the given temporary path if
+/// rewriting it succeeded, or otherwise remove the copy
+fn replace_archive(
+    archive: &Path,
+    temp_path: &Path,
+    result: Result<(), Error>,
+) -> Result<(), Error> {
+    let result = result.and_then(|()| {
+        fs::set_permissions(temp_path, fs::metadata(archive)?.permissions())?;
+        fs::rename(temp_path, archive)?;
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(temp_path);
+    }
+
+    result
+}
+
+/// Does the given path denote stdin or stdout rather than a file (ala `-f -`)?
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
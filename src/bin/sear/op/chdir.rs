@@ -1,28 +1,37 @@
 //! Change directory
 
-use crate::error::Error;
-use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-/// Support for optionally changing the current directory prior to performing
-/// an operation
-#[derive(Clone, Debug)]
+/// Directory given with `-C`, which paths on the local filesystem are
+/// resolved relative to.
+///
+/// Rather than changing the current directory of the whole process, paths are
+/// resolved individually, so files given on the command line can be relative
+/// to different directories (ala `tar -C dir1 foo -C dir2 bar`).
+#[derive(Clone, Debug, Default)]
 pub struct Chdir {
     path: Option<PathBuf>,
 }
 
 impl Chdir {
-    /// Create a new `Chdir` struct with an optional `PathBuf`
-    pub fn new(path: Option<PathBuf>) -> Self {
-        Self { path }
+    /// Change to the given directory, which is relative to this one (so
+    /// successive `-C` options accumulate, as with `tar`)
+    pub fn join(&self, path: impl AsRef<Path>) -> Self {
+        Self {
+            path: Some(self.resolve(path)),
+        }
     }
 
-    /// Perform the directory change operation if we're configured to
-    pub fn perform(&self) -> Result<(), Error> {
-        if let Some(ref path) = self.path {
-            env::set_current_dir(path)?;
-        }
+    /// Get the directory paths are resolved relative to
+    pub fn dir(&self) -> &Path {
+        self.path.as_deref().unwrap_or_else(|| Path::new("."))
+    }
 
-        Ok(())
+    /// Resolve the given path relative to this directory
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        match &self.path {
+            Some(dir) => dir.join(path),
+            None => path.as_ref().to_owned(),
+        }
     }
 }
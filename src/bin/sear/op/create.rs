@@ -1,38 +1,96 @@
 //! `sear` operations (crate or extract)
 
-use super::chdir::Chdir;
+use super::{chdir::Chdir, chdir_file_args, is_stdio, replace_archive, temp_path, transform_args};
 use crate::{
     command::SearCmd,
     error::{Error, ErrorKind},
     prelude::*,
 };
-use sear::{Builder, Entry, KeyRing};
+use abscissa_core::terminal::{status::Status, Color};
+use sear::{
+    crypto::stream::writer::{ChunkSize, MAX_THREADS},
+    entry::{
+        self,
+        attributes::ContentDetection,
+        digest::DigestReader,
+        permissions::mode::{self, ModeChange},
+        Kind,
+    },
+    exclude::Exclude,
+    snapshot::{Record, Snapshot},
+    transform::Transform,
+    Builder, Entry, KeyRing,
+};
 use std::{
-    fs::{File, OpenOptions},
+    collections::HashMap,
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
     path::{Path, PathBuf},
     process::exit,
+    thread,
 };
 
-/// Create a new archive
+/// Create a new archive (or append to or update an existing one)
 #[derive(Debug)]
 pub struct CreateOp {
-    /// Input/output archive file
+    /// Append to an existing archive rather than creating a new one
+    pub append: bool,
+
+    /// Output archive file (or `-` for stdout)
     pub archive: PathBuf,
 
-    /// Change to the given directory before archiving
-    pub chdir: Chdir,
+    /// Size of the chunks of the encrypted stream (when creating an archive)
+    pub chunk_size: ChunkSize,
+
+    /// How to detect the content types of added files
+    pub content_detection: ContentDetection,
+
+    /// Host to record as where the archive was created (overrides default)
+    pub creator_host: Option<String>,
 
-    /// Files to include in the archive
-    pub files: Vec<PathBuf>,
+    /// Username to record as the archive's creator (overrides default)
+    pub creator_username: Option<String>,
+
+    /// Patterns for files to exclude from the archive
+    pub exclude: Exclude,
+
+    /// Files to include in the archive (`-` reads a file from stdin), along
+    /// with the directories they're relative to
+    pub files: Vec<(Chdir, PathBuf)>,
 
     /// Encryption and signing keys
     pub keyring: KeyRing,
 
+    /// Store the index in the header, ahead of the contents of entries
+    pub index_first: bool,
+
+    /// Snapshot file used to create an incremental archive
+    pub listed_incremental: Option<PathBuf>,
+
+    /// Override the permissions of added files
+    pub mode: Option<ModeChange>,
+
+    /// Omit the username and host which created the archive
+    pub no_creator: bool,
+
     /// Preserve absolute pathnames
     pub preserve_pathnames: bool,
 
-    /// Preserve file permissions
-    pub preserve_permissions: bool,
+    /// Rewrites the paths of entries before they're archived
+    pub transform: Transform,
+
+    /// Number of threads to encrypt the archive with
+    pub threads: usize,
+
+    /// File mode creation mask applied by `--mode` clauses like `+r`
+    pub umask: u32,
+
+    /// Only append files which have changed since they were last archived
+    pub update: bool,
+
+    /// Name to archive the contents of stdin under
+    pub stdin_name: Option<PathBuf>,
 
     /// Enable verbose mode (i.e. print filenames)
     pub verbose: bool,
@@ -46,8 +104,53 @@ impl CreateOp {
             None => fail!(ErrorKind::Argument, "no -f option given"),
         };
 
-        let chdir = Chdir::new(args.chdir.as_ref().map(PathBuf::from));
-        let files = args.files.iter().map(PathBuf::from).collect();
+        if args.append || args.update {
+            ensure!(
+                !is_stdio(&archive),
+                ErrorKind::Argument,
+                "can't append to an archive on stdout (`-f -`)"
+            );
+
+            ensure!(
+                !args.index_first,
+                ErrorKind::Argument,
+                "--index-first can't be used when appending (the index is already placed)"
+            );
+
+            ensure!(
+                args.listed_incremental.is_none(),
+                ErrorKind::Argument,
+                "--listed-incremental can only be used when creating an archive (-c)"
+            );
+        }
+
+        let files = chdir_file_args(args)?;
+        let stdin_name = args.stdin_name.as_ref().map(PathBuf::from);
+
+        match files.iter().filter(|(_, path)| is_stdio(path)).count() {
+            0 => ensure!(
+                stdin_name.is_none(),
+                ErrorKind::Argument,
+                "--stdin-name given but no file is read from stdin (`-`)"
+            ),
+            1 => {
+                ensure!(
+                    stdin_name.is_some(),
+                    ErrorKind::Argument,
+                    "reading a file from stdin (`-`) requires --stdin-name"
+                );
+
+                ensure!(
+                    !args.index_first,
+                    ErrorKind::Argument,
+                    "--index-first can't be used with a file read from stdin (its length isn't known in advance)"
+                );
+            }
+            _ => fail!(
+                ErrorKind::Argument,
+                "only one file can be read from stdin (`-`)"
+            ),
+        }
 
         let mut keyring = KeyRing::new();
 
@@ -55,57 +158,396 @@ impl CreateOp {
             keyring.load_symmetric_key(key_path)?;
         }
 
+        let threads = match args.threads {
+            Some(0) => thread::available_parallelism()?.get().min(MAX_THREADS),
+            Some(threads) => threads,
+            None => 1,
+        };
+
+        let mut exclude = Exclude::new();
+        exclude.add_patterns(&args.exclude)?;
+
+        for path in &args.exclude_from {
+            exclude.add_file(path)?;
+        }
+
+        let mode = args.mode.as_ref().map(|m| m.parse()).transpose()?;
+
+        // Appends use the chunk size of the existing archive
+        let chunk_size = args
+            .chunk_size
+            .map(ChunkSize::try_from)
+            .transpose()?
+            .unwrap_or_default();
+
+        let content_detection = args
+            .content_type
+            .as_ref()
+            .map(|c| c.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        ensure!(
+            !args.no_creator || (args.creator_username.is_none() && args.creator_host.is_none()),
+            ErrorKind::Argument,
+            "--no-creator and --creator-username/--creator-host are orthogonal (pick one)"
+        );
+
         Ok(Self {
+            append: args.append || args.update,
             archive,
-            chdir,
+            chunk_size,
+            content_detection,
+            creator_host: args.creator_host.clone(),
+            creator_username: args.creator_username.clone(),
+            exclude,
             keyring,
             files,
+            index_first: args.index_first,
+            listed_incremental: args.listed_incremental.as_ref().map(PathBuf::from),
+            mode,
+            no_creator: args.no_creator,
             preserve_pathnames: args.preserve_pathnames,
-            preserve_permissions: args.preserve_permissions,
+            stdin_name,
+            transform: transform_args(args)?,
+            threads,
+            umask: mode::umask(),
+            update: args.update,
             verbose: args.verbose,
         })
     }
 
-    /// Create a new .sear archive
+    /// Create a new .sear archive (or append to an existing one)
     pub fn perform(&self) -> Result<(), Error> {
-        assert!(!self.preserve_pathnames, "-P option unsupported");
-        assert!(!self.preserve_permissions, "-p option unsupported");
-
         let symmetric_key = self.keyring.symmetric_key().unwrap_or_else(|| {
             status_err!("no symmetric key selected (use -K flag)");
             exit(1);
         });
 
-        let archive = File::create(&self.archive)?;
+        // Find everything which is being archived before touching the
+        // archive, so a missing input doesn't leave it truncated
+        let (entries, snapshot) = self.select_entries()?;
+
+        if self.append {
+            // Append to a copy of the archive, so the original is left intact
+            // if appending fails partway through
+            let temp_path = temp_path(&self.archive);
+            let mut original = File::open(&self.archive)?;
+            let mut archive = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&temp_path)?;
+
+            let result = io::copy(&mut original, &mut archive)
+                .map_err(Error::from)
+                .and_then(|_| Ok(Builder::reopen(archive, symmetric_key)?))
+                .and_then(|builder| self.build(builder, entries));
+
+            replace_archive(&self.archive, &temp_path, result)?;
+        } else {
+            let archive: Box<dyn Write> = if is_stdio(&self.archive) {
+                Box::new(BufWriter::new(io::stdout()))
+            } else {
+                Box::new(File::create(&self.archive)?)
+            };
+
+            let builder = Builder::new(archive, symmetric_key, self.chunk_size)?;
+            self.build(builder, entries)?;
+        }
+
+        // Only update the snapshot once the archive is complete
+        if let Some(snapshot_path) = &self.listed_incremental {
+            snapshot.save(snapshot_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Collect entries for the files being archived, along with the paths
+    /// they're read from and a snapshot of their state. With
+    /// `--listed-incremental`, only files which changed since the previous
+    /// snapshot are selected, preceded by tombstones for deleted ones.
+    fn select_entries(&self) -> Result<(Vec<(PathBuf, Entry)>, Snapshot), Error> {
+        if !self.preserve_pathnames
+            && self
+                .files
+                .iter()
+                .map(|(_, path)| path)
+                .chain(&self.stdin_name)
+                .any(|path| path.has_root())
+        {
+            self.print_status(
+                Status::new().bold().color(Color::Yellow).status("warning:"),
+                "Removing leading `/` from member names".to_owned(),
+            );
+        }
+
+        let mut entries = vec![];
+        let mut snapshot = Snapshot::new();
+
+        for (chdir, path) in &self.files {
+            self.collect_entries(chdir, path, &self.exclude, &mut entries, &mut snapshot)?;
+        }
+
+        if let Some(snapshot_path) = &self.listed_incremental {
+            let previous = Snapshot::load(snapshot_path)?;
+
+            entries.retain(|(_, entry)| match snapshot.get(&entry.path) {
+                Some(record) => previous.is_changed(&entry.path, record),
+                None => true,
+            });
 
-        // Change to the specified directory if one has been configured.
-        // Note this intentionally happens AFTER we have created the output file.
-        self.chdir.perform()?;
+            // Record deletions ahead of new files, so a file read from stdin
+            // isn't shadowed by a tombstone for the same path
+            let tombstones = previous
+                .deleted(&snapshot)
+                .into_iter()
+                .map(|path| Ok((path.clone(), Entry::tombstone(path)?)))
+                .collect::<Result<Vec<_>, Error>>()?;
 
-        // TODO(tarcieri): configurable chunk size (default parameter)
-        let mut builder = Builder::new(archive, symmetric_key, Default::default())?;
+            entries.splice(..0, tombstones);
+        }
+
+        Ok((entries, snapshot))
+    }
+
+    /// Add the selected entries to the archive being built and finish it
+    fn build<W: Write>(
+        &self,
+        mut builder: Builder<W>,
+        mut entries: Vec<(PathBuf, Entry)>,
+    ) -> Result<(), Error> {
+        builder
+            .content_detection(self.content_detection)
+            .transform(self.transform.clone())
+            .threads(self.threads)?;
 
-        for path in &self.files {
-            self.add_file(&mut builder, path)?;
+        if self.no_creator {
+            builder.host(None).username(None);
+        }
+
+        if let Some(host) = &self.creator_host {
+            builder.host(Some(host.clone()));
+        }
+
+        if let Some(username) = &self.creator_username {
+            builder.username(Some(username.clone()));
+        }
+
+        if self.update {
+            // Later entries with the same path shadow earlier ones
+            let archived: HashMap<&Path, &Entry> = builder
+                .entries()
+                .iter()
+                .map(|entry| (entry.path.as_path(), entry))
+                .collect();
+
+            let mut changed = Vec::with_capacity(entries.len());
+
+            for (path, entry) in entries {
+                // Archived paths have already been transformed
+                let archived_entry = match self.transform.apply(&entry.path)? {
+                    Some(transformed) => archived.get(transformed.as_path()),
+                    None => None,
+                };
+
+                let is_updated = match archived_entry {
+                    Some(archived_entry) => is_changed(&entry, archived_entry),
+                    None => true,
+                };
+
+                if is_stdio(&path) || is_updated {
+                    changed.push((path, entry));
+                }
+            }
+
+            entries = changed;
+        }
+
+        if self.index_first {
+            let index = entries
+                .iter()
+                .map(|(path, entry)| self.index_entry(path, entry.clone()))
+                .collect::<Result<_, _>>()?;
+
+            builder.index_in_header(index)?;
+        }
+
+        for (path, entry) in entries {
+            self.add_entry(&mut builder, &path, entry)?;
         }
 
         builder.finish()?;
         Ok(())
     }
 
-    /// Add a file to the given archive
-    fn add_file(&self, builder: &mut Builder<File>, path: &Path) -> Result<(), Error> {
-        let entry = Entry::for_file(path)?;
+    /// Collect entries for a file along with the paths they were read from,
+    /// recursing into directories, and record their state in a snapshot.
+    /// Excluded files (and the contents of excluded directories) are skipped.
+    ///
+    /// The file is read from `path` relative to the given directory, but is
+    /// archived under `path` itself.
+    fn collect_entries(
+        &self,
+        chdir: &Chdir,
+        path: &Path,
+        exclude: &Exclude,
+        entries: &mut Vec<(PathBuf, Entry)>,
+        snapshot: &mut Snapshot,
+    ) -> Result<(), Error> {
+        let (mut entry, source, record) = match &self.stdin_name {
+            Some(stdin_name) if is_stdio(path) => {
+                (Entry::for_stream(stdin_name)?, path.to_owned(), None)
+            }
+            _ => {
+                let source = chdir.resolve(path);
+                let metadata = fs::symlink_metadata(&source)?;
 
-        status_ok!(
-            "Adding",
-            "{} ({})",
-            path.display(),
-            entry.length_formatted()
-        );
+                if exclude.is_excluded(path, metadata.is_dir()) {
+                    return Ok(());
+                }
+
+                // Archive the file under the path it was given as, rather
+                // than the one it was read from
+                let mut entry = Entry::for_metadata(&source, &metadata)?;
+                entry.path = path.to_owned();
+
+                (entry, source, Some(Record::for_metadata(&metadata)))
+            }
+        };
+
+        entry.path = entry::normalize_path(&entry.path, self.preserve_pathnames)?;
+
+        if let Some(mode) = &self.mode {
+            entry.permissions.mode = mode.apply(entry.permissions.mode, self.umask)?;
+        }
+
+        let is_dir = entry.kind == Kind::Directory;
+
+        // Directories like `.` or `/` normalize to an empty path, in which
+        // case only their contents are archived
+        if !entry.path.as_os_str().is_empty() {
+            if let Some(record) = record {
+                snapshot.insert(entry.path.clone(), record);
+            }
+
+            entries.push((source.clone(), entry));
+        }
+
+        if is_dir {
+            let mut children = fs::read_dir(&source)?
+                .map(|child| child.map(|c| path.join(c.file_name())))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // Sort children so archives are reproducible
+            children.sort();
+
+            let exclude = exclude.enter_dir_in(chdir.dir(), path)?;
+
+            for child in &children {
+                self.collect_entries(chdir, child, &exclude, entries, snapshot)?;
+            }
+        }
 
-        let mut file = OpenOptions::new().read(true).open(path)?;
-        builder.append(entry, &mut file)?;
         Ok(())
     }
+
+    /// Detect the content type and compute the digest of a regular file
+    /// ahead of archiving it, for use when the index is written before the
+    /// file's contents
+    fn index_entry(&self, path: &Path, mut entry: Entry) -> Result<Entry, Error> {
+        if entry.kind != Kind::File {
+            return Ok(entry);
+        }
+
+        let mut reader = DigestReader::new(File::open(path)?);
+        let mut head = vec![];
+
+        reader
+            .by_ref()
+            .take(ChunkSize::default() as u64)
+            .read_to_end(&mut head)?;
+
+        io::copy(&mut reader, &mut io::sink())?;
+        entry.digest = Some(reader.digest());
+
+        if let Some(content_type) = self.content_detection.detect(&entry.path, &head)? {
+            entry.attributes.content_type = content_type;
+        }
+
+        Ok(entry)
+    }
+
+    /// Add an entry to the given archive, reading its contents from `path`
+    fn add_entry<W: Write>(
+        &self,
+        builder: &mut Builder<W>,
+        path: &Path,
+        entry: Entry,
+    ) -> Result<(), Error> {
+        if self.verbose {
+            let (status, msg) = if entry.kind == Kind::Deleted {
+                ("Deleted", entry.path.display().to_string())
+            } else {
+                // The length of stdin isn't known until it's been read
+                let length = if is_stdio(path) {
+                    "stdin".to_owned()
+                } else {
+                    entry.length_formatted()
+                };
+
+                ("Adding", format!("{} ({})", entry.path.display(), length))
+            };
+
+            self.print_status(
+                Status::new()
+                    .justified()
+                    .bold()
+                    .color(Color::Green)
+                    .status(status),
+                msg,
+            );
+        }
+
+        match entry.kind {
+            Kind::File if is_stdio(path) => builder.append(entry, io::stdin().lock())?,
+            Kind::File => {
+                let mut file = OpenOptions::new().read(true).open(path)?;
+                builder.append(entry, &mut file)?;
+            }
+            Kind::Directory | Kind::Link { .. } | Kind::Deleted => {
+                builder.append(entry, io::empty())?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print a status message, to stderr if the archive is being written to
+    /// stdout (so the message doesn't corrupt the archive)
+    fn print_status(&self, status: Status, msg: String) {
+        if is_stdio(&self.archive) {
+            status.print_stderr(msg)
+        } else {
+            status.print_stdout(msg)
+        }
+        .unwrap();
+    }
+}
+
+/// Has a file changed since it was archived, i.e. is it newer or a different
+/// size? Files whose modification times are unknown are assumed to be newer.
+fn is_changed(entry: &Entry, archived: &Entry) -> bool {
+    if entry.length != archived.length {
+        return true;
+    }
+
+    match (
+        entry.attributes.modified_at,
+        archived.attributes.modified_at,
+    ) {
+        (Some(modified_at), Some(archived_at)) => modified_at > archived_at,
+        _ => true,
+    }
 }
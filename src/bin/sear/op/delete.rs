@@ -0,0 +1,121 @@
+//! Delete entries from an existing archive
+
+use super::{file_args, is_stdio, replace_archive, temp_path};
+use crate::{
+    command::SearCmd,
+    error::{Error, ErrorKind},
+    prelude::*,
+};
+use sear::{Archive, KeyRing};
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+/// Delete entries from an existing archive
+#[derive(Debug)]
+pub struct DeleteOp {
+    /// Archive file to delete entries from
+    pub archive: PathBuf,
+
+    /// Files to delete from the archive (including the contents of directories)
+    pub files: Vec<PathBuf>,
+
+    /// Encryption and signing keys
+    pub keyring: KeyRing,
+
+    /// Enable verbose mode (i.e. print filenames)
+    pub verbose: bool,
+}
+
+impl DeleteOp {
+    /// Initialize a delete operation from command-line arguments
+    pub fn new(args: &SearCmd) -> Result<Self, Error> {
+        let archive = match args.archive {
+            Some(ref path) => PathBuf::from(path),
+            None => fail!(ErrorKind::Argument, "no -f option given"),
+        };
+
+        ensure!(
+            !is_stdio(&archive),
+            ErrorKind::Argument,
+            "can't delete from an archive on stdin/stdout (`-f -`)"
+        );
+
+        let files = file_args(args)?;
+
+        ensure!(
+            !files.is_empty(),
+            ErrorKind::Argument,
+            "no files given to delete"
+        );
+
+        let mut keyring = KeyRing::new();
+
+        if let Some(key_path) = &args.encryption_key {
+            keyring.load_symmetric_key(key_path)?;
+        }
+
+        Ok(Self {
+            archive,
+            files,
+            keyring,
+            verbose: args.verbose,
+        })
+    }
+
+    /// Rewrite an existing .sear archive without the selected entries
+    pub fn perform(&self) -> Result<(), Error> {
+        let symmetric_key = self.keyring.symmetric_key().unwrap_or_else(|| {
+            status_err!("no symmetric key selected (use -K flag)");
+            exit(1);
+        });
+
+        let mut archive = Archive::open(File::open(&self.archive)?, symmetric_key)?;
+
+        for file in &self.files {
+            ensure!(
+                archive
+                    .entries()
+                    .iter()
+                    .any(|entry| entry.path.starts_with(file)),
+                ErrorKind::Argument,
+                "`{}` not found in archive",
+                file.display()
+            );
+        }
+
+        // Write the rewritten archive alongside the original, replacing it
+        // only once it's been written successfully
+        let temp_path = temp_path(&self.archive);
+        let output = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+
+        let result = archive
+            .rewrite(output, symmetric_key, |entry| {
+                let deleted = self.is_selected(&entry.path);
+
+                if deleted && self.verbose {
+                    status_ok!(
+                        "Deleting",
+                        "{} ({})",
+                        entry.path.display(),
+                        entry.length_formatted()
+                    );
+                }
+
+                !deleted
+            })
+            .map_err(Error::from);
+
+        replace_archive(&self.archive, &temp_path, result)
+    }
+
+    /// Was the given path selected for deletion on the command line?
+    fn is_selected(&self, path: &Path) -> bool {
+        self.files.iter().any(|file| path.starts_with(file))
+    }
+}
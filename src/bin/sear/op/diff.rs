@@ -0,0 +1,330 @@
+//! Compare an existing archive against the local filesystem or another archive
+
+use super::{chdir::Chdir, chdir_arg, file_args, is_stdio, open_archive, InputArchive};
+use crate::{
+    command::{DiffCmd, SearCmd},
+    error::{Error, ErrorKind},
+    prelude::*,
+};
+use sear::{
+    archive,
+    crypto::kdf,
+    diff::{self, Difference},
+    entry::{self, Kind},
+    Archive, Entry, KeyRing,
+};
+use serde_json::json;
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::exit,
+    str::FromStr,
+};
+
+/// Compare an existing archive against the local filesystem or another archive
+#[derive(Debug)]
+pub struct DiffOp {
+    /// Archive to compare against instead of the local filesystem
+    pub against: Option<PathBuf>,
+
+    /// Encryption key for the archive being compared against (if different)
+    pub against_keyring: Option<KeyRing>,
+
+    /// Input archive file (or `-` for stdin)
+    pub archive: PathBuf,
+
+    /// Directory containing the files to compare against
+    pub chdir: Chdir,
+
+    /// Files to compare (or all files if empty)
+    pub files: Vec<PathBuf>,
+
+    /// Format to report differences in
+    pub format: Format,
+
+    /// Encryption and signing keys
+    pub keyring: KeyRing,
+}
+
+impl DiffOp {
+    /// Initialize a diff operation from command-line arguments
+    pub fn new(args: &SearCmd) -> Result<Self, Error> {
+        let archive = match args.archive {
+            Some(ref path) => PathBuf::from(path),
+            None => fail!(ErrorKind::Argument, "no -f option given"),
+        };
+
+        Ok(Self {
+            against: None,
+            against_keyring: None,
+            archive,
+            chdir: chdir_arg(args)?,
+            files: file_args(args)?,
+            format: parse_format(args.format.as_deref())?,
+            keyring: load_keyring(args.encryption_key.as_deref())?,
+        })
+    }
+
+    /// Initialize a diff operation between two archives given to the
+    /// `sear diff` subcommand
+    pub fn for_archives(args: &DiffCmd) -> Result<Self, Error> {
+        let (archive, against, files) = match args.args.as_slice() {
+            [archive, against, files @ ..] => (archive, against, files),
+            _ => fail!(
+                ErrorKind::Argument,
+                "`sear diff` takes two archives to compare"
+            ),
+        };
+
+        ensure!(
+            !is_stdio(Path::new(against)),
+            ErrorKind::Argument,
+            "can't read the archive to compare against from stdin"
+        );
+
+        let against_keyring = args
+            .new_key
+            .as_deref()
+            .map(|key_path| load_keyring(Some(key_path)))
+            .transpose()?;
+
+        Ok(Self {
+            against: Some(PathBuf::from(against)),
+            against_keyring,
+            archive: PathBuf::from(archive),
+            chdir: Chdir::default(),
+            files: files.iter().map(PathBuf::from).collect(),
+            format: parse_format(args.format.as_deref())?,
+            keyring: load_keyring(args.encryption_key.as_deref())?,
+        })
+    }
+
+    /// Compare an existing .sear archive against the local filesystem (or
+    /// another archive), exiting with a non-zero status if they differ
+    pub fn perform(&self) -> Result<(), Error> {
+        let symmetric_key = self.keyring.symmetric_key().unwrap_or_else(|| {
+            status_err!("no symmetric key selected (use -K flag)");
+            exit(1);
+        });
+
+        let differs = match &self.against {
+            Some(against) => self.diff_archives(symmetric_key, against)?,
+            None => self.diff_filesystem(symmetric_key)?,
+        };
+
+        if differs {
+            exit(1);
+        }
+
+        Ok(())
+    }
+
+    /// Compare the archive's index against that of another archive,
+    /// reporting any differences. Returns whether any were found.
+    fn diff_archives(&self, key: &kdf::Key, against: &Path) -> Result<bool, Error> {
+        let against_key = match &self.against_keyring {
+            Some(keyring) => keyring.symmetric_key().unwrap(),
+            None => key,
+        };
+
+        let old = read_index(&self.archive, key)?;
+        let new = Archive::open(File::open(against)?, against_key)?
+            .entries()
+            .to_vec();
+
+        let mut differs = false;
+
+        for (path, differences) in diff::compare_indexes(&old, &new) {
+            if self.is_selected(&path) {
+                for line in self.format.report(&path, &differences)? {
+                    println!("{}", line);
+                }
+
+                differs = true;
+            }
+        }
+
+        Ok(differs)
+    }
+
+    /// Compare the archive against the local filesystem, reporting any
+    /// differences. Returns whether any were found.
+    fn diff_filesystem(&self, key: &kdf::Key) -> Result<bool, Error> {
+        let mut differs = false;
+
+        match open_archive(&self.archive, key)? {
+            InputArchive::File(mut archive) => {
+                let shadowed = archive::shadowed(archive.entries());
+
+                for (index, shadowed) in shadowed.into_iter().enumerate() {
+                    if !shadowed {
+                        let entry = archive.entries()[index].clone();
+                        differs |= self.diff_entry(&entry, archive.read_entry(index)?)?;
+                    }
+                }
+            }
+            InputArchive::Stream(mut archive) => {
+                let shadowed = archive::shadowed(archive.entries());
+                let mut index = 0;
+
+                while let Some((entry, reader)) = archive.next_entry()? {
+                    if !shadowed[index] {
+                        differs |= self.diff_entry(&entry, reader)?;
+                    }
+
+                    index += 1;
+                }
+            }
+        }
+
+        Ok(differs)
+    }
+
+    /// Compare an entry against the local filesystem if it was selected on
+    /// the command line, reporting any differences. Returns whether any
+    /// were found.
+    fn diff_entry(&self, entry: &Entry, reader: impl Read) -> Result<bool, Error> {
+        if !self.is_selected(&entry.path) {
+            return Ok(false);
+        }
+
+        let differences = self.compare(entry, reader)?;
+
+        for line in self.format.report(&entry.path, &differences)? {
+            println!("{}", line);
+        }
+
+        Ok(!differences.is_empty())
+    }
+
+    /// Compare an entry against the file at the same path, comparing the
+    /// contents of regular files if their metadata doesn't already differ
+    fn compare(&self, entry: &Entry, reader: impl Read) -> Result<Vec<Difference>, Error> {
+        let path = self.chdir.resolve(&entry.path);
+
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            // Files deleted in the archive are expected to be missing
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(if entry.kind == Kind::Deleted {
+                    vec![]
+                } else {
+                    vec![Difference::Missing]
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let file = Entry::for_metadata(&path, &metadata)?;
+        let mut differences = diff::compare(entry, &file);
+
+        if entry.kind == Kind::File
+            && !differences.contains(&Difference::Kind)
+            && !differences.contains(&Difference::Length)
+            && !diff::same_contents(reader, File::open(&path)?)?
+        {
+            differences.push(Difference::Contents);
+        }
+
+        Ok(differences)
+    }
+
+    /// Was the given path selected for comparison on the command line?
+    fn is_selected(&self, path: &Path) -> bool {
+        self.files.is_empty() || self.files.iter().any(|file| path.starts_with(file))
+    }
+}
+
+/// Load the encryption key at the given path, if any
+fn load_keyring(key_path: Option<&Path>) -> Result<KeyRing, Error> {
+    let mut keyring = KeyRing::new();
+
+    if let Some(key_path) = key_path {
+        keyring.load_symmetric_key(key_path)?;
+    }
+
+    Ok(keyring)
+}
+
+/// Parse the format given with `--format`, if any
+fn parse_format(format: Option<&str>) -> Result<Format, Error> {
+    Ok(format.map(str::parse).transpose()?.unwrap_or_default())
+}
+
+/// Read the index of the archive at the given path (or `-` for stdin)
+fn read_index(path: &Path, key: &kdf::Key) -> Result<Vec<Entry>, Error> {
+    let entries = match open_archive(path, key)? {
+        InputArchive::File(archive) => archive.entries().to_vec(),
+        InputArchive::Stream(archive) => archive.entries().to_vec(),
+    };
+
+    Ok(entries)
+}
+
+/// Formats to report differences in
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    /// Human-readable lines ala `tar -d`, e.g. `foo.txt: Size differs`
+    #[default]
+    Text,
+
+    /// One JSON object per path, e.g.
+    /// `{"path":"foo.txt","differences":["size"]}`. Paths which aren't valid
+    /// UTF-8 also have a `path_bytes` field with their raw bytes.
+    Json,
+}
+
+impl Format {
+    /// Format the differences found for the given path as lines of output
+    pub fn report(self, path: &Path, differences: &[Difference]) -> Result<Vec<String>, Error> {
+        if differences.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let lines = match self {
+            Format::Text => differences
+                .iter()
+                .map(|difference| format!("{}: {}", path.display(), difference.description()))
+                .collect(),
+            Format::Json => {
+                let differences = differences
+                    .iter()
+                    .map(|difference| difference.as_str())
+                    .collect::<Vec<_>>();
+
+                let mut report = json!({
+                    "path": path.to_string_lossy(),
+                    "differences": differences,
+                });
+
+                // Keep paths which aren't valid UTF-8 intact
+                let (_, path_bytes) = entry::encode_path(path)?;
+
+                if !path_bytes.is_empty() {
+                    report["path_bytes"] = json!(path_bytes);
+                }
+
+                vec![report.to_string()]
+            }
+        };
+
+        Ok(lines)
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => fail!(
+                ErrorKind::Argument,
+                "unknown format: `{}` (expected text or json)",
+                s
+            ),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Extract files from an existing archive
+
+use super::{chdir::Chdir, chdir_arg, file_args, open_archive, transform_args, InputArchive};
+use crate::{
+    command::SearCmd,
+    error::{Error, ErrorKind},
+    prelude::*,
+};
+use sear::{
+    archive,
+    entry::{permissions::SELinuxRestore, Kind},
+    transform::Transform,
+    Entry, Extractor, KeyRing,
+};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+/// Extract an existing archive
+#[derive(Debug)]
+pub struct ExtractOp {
+    /// Extract every version of files with multiple entries, rather than
+    /// only the latest
+    pub all_versions: bool,
+
+    /// Input archive file (or `-` for stdin)
+    pub archive: PathBuf,
+
+    /// Directory to extract into
+    pub chdir: Chdir,
+
+    /// Files to extract from the archive (or all files if empty)
+    pub files: Vec<PathBuf>,
+
+    /// Encryption and signing keys
+    pub keyring: KeyRing,
+
+    /// Use numerical UIDs/GIDs rather than names when restoring ownership
+    pub numeric_owner: bool,
+
+    /// Preserve file permissions, including setuid/setgid/sticky bits
+    pub preserve_permissions: bool,
+
+    /// Restore file ownership (`None` uses the default for the current user)
+    pub same_owner: Option<bool>,
+
+    /// How to restore SELinux labels
+    pub selinux: SELinuxRestore,
+
+    /// Rewrites the paths of entries before they're extracted
+    pub transform: Transform,
+
+    /// Enable verbose mode (i.e. print filenames)
+    pub verbose: bool,
+}
+
+impl ExtractOp {
+    /// Initialize an extract operation from command-line arguments
+    pub fn new(args: &SearCmd) -> Result<Self, Error> {
+        let archive = match args.archive {
+            Some(ref path) => PathBuf::from(path),
+            None => fail!(ErrorKind::Argument, "no -f option given"),
+        };
+
+        let chdir = chdir_arg(args)?;
+        let files = file_args(args)?;
+
+        let mut keyring = KeyRing::new();
+
+        if let Some(key_path) = &args.encryption_key {
+            keyring.load_symmetric_key(key_path)?;
+        }
+
+        let selinux = match (args.selinux, args.selinux_relabel) {
+            (true, true) => fail!(
+                ErrorKind::Argument,
+                "--selinux and --selinux-relabel are orthogonal (pick one)"
+            ),
+            (true, false) => SELinuxRestore::Archived,
+            (false, true) => SELinuxRestore::Default,
+            (false, false) => SELinuxRestore::Skip,
+        };
+
+        let same_owner = match (args.same_owner, args.no_same_owner) {
+            (true, true) => fail!(
+                ErrorKind::Argument,
+                "--same-owner and --no-same-owner are orthogonal (pick one)"
+            ),
+            (true, false) => Some(true),
+            (false, true) => Some(false),
+            (false, false) => None,
+        };
+
+        Ok(Self {
+            all_versions: args.all_versions,
+            archive,
+            chdir,
+            files,
+            keyring,
+            numeric_owner: args.numeric_owner,
+            preserve_permissions: args.preserve_permissions,
+            same_owner,
+            selinux,
+            transform: transform_args(args)?,
+            verbose: args.verbose,
+        })
+    }
+
+    /// Extract an existing .sear archive
+    pub fn perform(&self) -> Result<(), Error> {
+        let symmetric_key = self.keyring.symmetric_key().unwrap_or_else(|| {
+            status_err!("no symmetric key selected (use -K flag)");
+            exit(1);
+        });
+
+        let mut extractor = self.extractor()?;
+
+        match open_archive(&self.archive, symmetric_key)? {
+            InputArchive::File(mut archive) => {
+                let shadowed = archive::shadowed(archive.entries());
+
+                for (index, shadowed) in shadowed.into_iter().enumerate() {
+                    let entry = archive.entries()[index].clone();
+
+                    if (self.all_versions || !shadowed) && self.is_selected(&entry.path) {
+                        self.extract_entry(&mut extractor, &entry, archive.read_entry(index)?)?;
+                    }
+                }
+            }
+            // Streamed archives can't be seeked, so their entries are
+            // extracted in order as they're read
+            InputArchive::Stream(mut archive) => {
+                let shadowed = archive::shadowed(archive.entries());
+                let mut index = 0;
+
+                while let Some((entry, reader)) = archive.next_entry()? {
+                    if self.all_versions || !shadowed[index] {
+                        self.extract_entry(&mut extractor, &entry, reader)?;
+                    }
+
+                    index += 1;
+                }
+            }
+        }
+
+        extractor.finish()?;
+        Ok(())
+    }
+
+    /// Initialize an extractor for the destination directory
+    fn extractor(&self) -> Result<Extractor, Error> {
+        let mut extractor = Extractor::new(self.chdir.dir())?;
+        extractor
+            .numeric_owner(self.numeric_owner)
+            .preserve_permissions(self.preserve_permissions)
+            .selinux(self.selinux)
+            .transform(self.transform.clone());
+
+        if let Some(same_owner) = self.same_owner {
+            extractor.same_owner(same_owner);
+        }
+
+        Ok(extractor)
+    }
+
+    /// Extract an entry if it was selected on the command line
+    fn extract_entry(
+        &self,
+        extractor: &mut Extractor,
+        entry: &Entry,
+        reader: impl Read,
+    ) -> Result<(), Error> {
+        if !self.is_selected(&entry.path) {
+            return Ok(());
+        }
+
+        if self.verbose {
+            if entry.kind == Kind::Deleted {
+                status_ok!("Removing", "{}", entry.path.display());
+            } else {
+                status_ok!(
+                    "Extracting",
+                    "{} ({})",
+                    entry.path.display(),
+                    entry.length_formatted()
+                );
+            }
+        }
+
+        extractor.extract(entry, reader)?;
+        Ok(())
+    }
+
+    /// Was the given path selected for extraction on the command line?
+    fn is_selected(&self, path: &Path) -> bool {
+        self.files.is_empty() || self.files.iter().any(|file| path.starts_with(file))
+    }
+}
@@ -0,0 +1,162 @@
+//! List the contents of an existing archive
+
+use super::{file_args, open_archive, InputArchive};
+use crate::{
+    command::SearCmd,
+    error::{Error, ErrorKind},
+    prelude::*,
+};
+use chrono::{DateTime, Local};
+use sear::{archive, entry::Kind, Entry, KeyRing};
+use std::{
+    path::{Path, PathBuf},
+    process::exit,
+};
+use tai64::TAI64N;
+
+/// List the contents of an existing archive
+#[derive(Debug)]
+pub struct ListOp {
+    /// List every version of files with multiple entries, rather than only
+    /// the latest
+    pub all_versions: bool,
+
+    /// Input archive file (or `-` for stdin)
+    pub archive: PathBuf,
+
+    /// Files to list from the archive (or all files if empty)
+    pub files: Vec<PathBuf>,
+
+    /// Encryption and signing keys
+    pub keyring: KeyRing,
+
+    /// Enable verbose mode (i.e. print `ls -l`-style details)
+    pub verbose: bool,
+}
+
+impl ListOp {
+    /// Initialize a list operation from command-line arguments
+    pub fn new(args: &SearCmd) -> Result<Self, Error> {
+        let archive = match args.archive {
+            Some(ref path) => PathBuf::from(path),
+            None => fail!(ErrorKind::Argument, "no -f option given"),
+        };
+
+        let files = file_args(args)?;
+
+        let mut keyring = KeyRing::new();
+
+        if let Some(key_path) = &args.encryption_key {
+            keyring.load_symmetric_key(key_path)?;
+        }
+
+        Ok(Self {
+            all_versions: args.all_versions,
+            archive,
+            files,
+            keyring,
+            verbose: args.verbose,
+        })
+    }
+
+    /// List the contents of an existing .sear archive
+    pub fn perform(&self) -> Result<(), Error> {
+        let symmetric_key = self.keyring.symmetric_key().unwrap_or_else(|| {
+            status_err!("no symmetric key selected (use -K flag)");
+            exit(1);
+        });
+
+        match open_archive(&self.archive, symmetric_key)? {
+            InputArchive::File(archive) => {
+                let creator =
+                    format_creator(archive.created_at(), archive.username(), archive.host());
+                self.list(&creator, archive.entries());
+            }
+            InputArchive::Stream(archive) => {
+                let creator =
+                    format_creator(archive.created_at(), archive.username(), archive.host());
+                self.list(&creator, archive.entries());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// List the given entries, preceded by who created the archive (if verbose)
+    fn list(&self, creator: &str, entries: &[Entry]) {
+        if self.verbose {
+            status_info!("Created", "{}", creator);
+        }
+
+        let shadowed = archive::shadowed(entries);
+
+        for (entry, shadowed) in entries.iter().zip(shadowed) {
+            if (shadowed && !self.all_versions) || !self.is_selected(&entry.path) {
+                continue;
+            }
+
+            if self.verbose {
+                println!("{}", format_long(entry));
+            } else {
+                println!("{}", entry.path.display());
+            }
+        }
+    }
+
+    /// Was the given path selected for listing on the command line?
+    fn is_selected(&self, path: &Path) -> bool {
+        self.files.is_empty() || self.files.iter().any(|file| path.starts_with(file))
+    }
+}
+
+/// Format when and by whom an archive was created, e.g.:
+///
+/// `2020-01-01 12:00 by alice@example.com`
+fn format_creator(
+    created_at: Option<TAI64N>,
+    username: Option<&str>,
+    host: Option<&str>,
+) -> String {
+    format!(
+        "{} by {}@{}",
+        format_date(created_at),
+        username.unwrap_or("?"),
+        host.unwrap_or("?")
+    )
+}
+
+/// Format a timestamp in local time, e.g. `2020-01-01 12:00`
+fn format_date(timestamp: Option<TAI64N>) -> String {
+    timestamp
+        .map(|t| {
+            DateTime::<Local>::from(t.to_datetime_utc())
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|| "????-??-?? ??:??".to_owned())
+}
+
+/// Format an entry ala `tar -tv`, e.g.:
+///
+/// `-rw-r--r-- alice/staff      1024 2020-01-01 12:00 foo.txt`
+fn format_long(entry: &Entry) -> String {
+    let mut line = format!(
+        "{} {} {:>9} {} {}",
+        entry.permissions.mode.ls_formatted(),
+        entry.owner,
+        entry.length,
+        format_date(entry.attributes.modified_at),
+        entry.path.display()
+    );
+
+    match &entry.kind {
+        Kind::Link { symbolic, target } => {
+            let arrow = if *symbolic { "->" } else { "link to" };
+            line.push_str(&format!(" {} {}", arrow, target.display()));
+        }
+        Kind::Deleted => line.push_str(" (deleted)"),
+        Kind::File | Kind::Directory => (),
+    }
+
+    line
+}
@@ -5,4 +5,5 @@
 pub use abscissa_core::prelude::*;
 
 /// Application state accessors
+#[allow(unused_imports)]
 pub use crate::application::{app_config, app_reader, app_writer};
@@ -1,17 +1,25 @@
 //! `.sear` archive builder
 
 use crate::{
+    archive::Archive,
     crypto::{
         kdf,
         stream::{self, writer::ChunkSize},
+        symmetric,
     },
-    entry::Entry,
+    entry::{attributes::ContentDetection, digest::DigestReader, Entry, Kind},
     error::{Error, ErrorKind},
-    protos::{Footer, Header, Index, MessageExt, Metadata, Tai64n},
+    protos::{header::MetadataLocation, Footer, Header, Index, MessageExt, Metadata, Tai64n},
+    transform::Transform,
     uuid,
 };
-use anomaly::{ensure, fail};
-use std::{convert::TryInto, io};
+use anomaly::{ensure, fail, format_err};
+use std::{
+    convert::{TryFrom, TryInto},
+    fs::File,
+    io::{self, Seek, SeekFrom},
+};
+use tai64::TAI64N;
 
 /// File signature found at the beginning of sear archives which identifies
 /// the format.
@@ -23,18 +31,72 @@ use std::{convert::TryInto, io};
 /// may not be compatible with other `0`-versioned tooling.
 pub const MAGIC_BYTES: &[u8; 6] = b"sear:0";
 
-/// Archive builder
+/// Filter deciding which entries are added to an archive
+type Filter = Box<dyn FnMut(&Entry) -> bool + Send>;
+
+/// Archive builder.
+///
+/// By default the index of entries is stored at the end of the archive,
+/// after their contents. Alternatively, if the entries are known in
+/// advance, the index can be stored at the start of the archive with
+/// [`Builder::index_in_header`] so it can be extracted as it's streamed.
 pub struct Builder<W: io::Write> {
+    /// Number of entries appended so far (when the index is in the header)
+    appended: usize,
+
+    /// Size of chunks in the encrypted stream
+    chunk_size: ChunkSize,
+
+    /// How to detect the content type of regular files
+    content_detection: ContentDetection,
+
+    /// Date the archive was created (if already known, i.e. when appending)
+    created_at: Option<TAI64N>,
+
     /// Entries within the archive
     entries: Vec<Entry>,
 
-    /// Encrypted stream writer which is outputting the archive
-    writer: stream::Writer<W>,
+    /// Filter deciding which entries are added to the archive
+    filter: Option<Filter>,
+
+    /// Host the archive is being built on
+    host: Option<String>,
+
+    /// Is the index stored in the header (as opposed to the footer)?
+    index_in_header: bool,
+
+    /// Symmetric key derived for this archive (until the stream is started)
+    key: Option<symmetric::Key>,
+
+    /// Segments of the encrypted stream with their own nonce prefixes
+    segments: Vec<stream::Segment>,
+
+    /// Encrypted stream writer which is outputting the archive (once the
+    /// header has been written)
+    stream: Option<stream::Writer<W>>,
+
+    /// Number of threads to encrypt the archive's contents with
+    threads: usize,
+
+    /// Rewrites the paths of entries before they're added to the index
+    transform: Transform,
+
+    /// User who is building the archive
+    username: Option<String>,
+
+    /// Random UUID which uniquely identifies this archive
+    uuid: String,
+
+    /// Output writer (until the header has been written)
+    writer: Option<W>,
 }
 
 impl<W: io::Write> Builder<W> {
-    /// Create a new archive builder wrapping the given writer
-    pub fn new(mut writer: W, key: &kdf::Key, chunk_size: ChunkSize) -> Result<Self, Error> {
+    /// Create a new archive builder wrapping the given writer.
+    ///
+    /// Nothing is written until the first entry is appended (or the archive
+    /// is finished), so the builder can be configured beforehand.
+    pub fn new(writer: W, key: &kdf::Key, chunk_size: ChunkSize) -> Result<Self, Error> {
         // Generate random UUID identifying this archive
         let uuid = uuid::new_v4().to_string();
 
@@ -42,47 +104,199 @@ impl<W: io::Write> Builder<W> {
         // root key and the random UUID as a "nonce"
         let derived_key = key.derive_symmetric_key(&uuid);
 
-        // Write 6-byte sear archive magic identifier
-        writer.write_all(MAGIC_BYTES)?;
+        Ok(Self {
+            appended: 0,
+            chunk_size,
+            content_detection: ContentDetection::default(),
+            created_at: None,
+            entries: vec![],
+            filter: None,
+            host: current_host(),
+            index_in_header: false,
+            key: Some(derived_key),
+            segments: vec![],
+            stream: None,
+            threads: 1,
+            transform: Transform::default(),
+            username: current_username(),
+            uuid,
+            writer: Some(writer),
+        })
+    }
 
-        let header = Header {
-            uuid: pad_with_newlines(&uuid),
-            chunk_size: chunk_size as u64,
-            encryption_key_fingerprint: "".to_owned(),
-            signing_key_fingerprint: "".to_owned(),
-        }
-        .to_vec()?;
+    /// Configure how the content types of regular files are detected
+    pub fn content_detection(&mut self, content_detection: ContentDetection) -> &mut Self {
+        self.content_detection = content_detection;
+        self
+    }
+
+    /// Set the date recorded as when the archive was created (defaults to
+    /// when the builder is finished)
+    pub fn created_at(&mut self, created_at: TAI64N) -> &mut Self {
+        self.created_at = Some(created_at);
+        self
+    }
+
+    /// Only add entries to the archive for which the given filter returns
+    /// `true` (e.g. to apply an [`Exclude`] set). Other entries are skipped
+    /// without reading their contents.
+    ///
+    /// [`Exclude`]: crate::exclude::Exclude
+    pub fn filter(&mut self, filter: impl FnMut(&Entry) -> bool + Send + 'static) -> &mut Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Set the host recorded as where the archive was created, or `None` to
+    /// omit it (defaults to the local hostname)
+    pub fn host(&mut self, host: Option<String>) -> &mut Self {
+        self.host = host;
+        self
+    }
 
+    /// Set the username recorded as the archive's creator, or `None` to omit
+    /// it (defaults to the effective user of this process)
+    pub fn username(&mut self, username: Option<String>) -> &mut Self {
+        self.username = username;
+        self
+    }
+
+    /// Encrypt the contents of the archive on the given number of threads
+    /// (defaults to 1, i.e. the calling thread, and at most
+    /// [`stream::writer::MAX_THREADS`])
+    pub fn threads(&mut self, threads: usize) -> Result<&mut Self, Error> {
         ensure!(
-            header.len() <= std::u16::MAX as usize,
-            ErrorKind::Overflow,
-            "oversized header: {}-bytes",
-            header.len()
+            threads > 0 && threads <= stream::writer::MAX_THREADS,
+            ErrorKind::Argument,
+            "archive must be encrypted with between 1 and {} threads (got {})",
+            stream::writer::MAX_THREADS,
+            threads
         );
 
-        // Write `u16` header length in little endian
-        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+        if let Some(stream) = &mut self.stream {
+            stream.threads(threads)?;
+        }
 
-        // Write serialized `sear.header.Header` proto
-        writer.write_all(&header)?;
+        self.threads = threads;
+        Ok(self)
+    }
 
-        let stream_writer = stream::Writer::new(
-            writer,
-            derived_key,
-            uuid.as_bytes(),
-            compute_aad(),
-            chunk_size,
+    /// Rewrite the paths of entries (and the targets of hard links) as
+    /// they're added to the archive. Entries with nothing left of their
+    /// paths are omitted.
+    pub fn transform(&mut self, transform: Transform) -> &mut Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Get the entries in the archive so far, including any which were
+    /// already present in an archive which was reopened to append to it
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Store the index of entries in the header, ahead of their contents.
+    ///
+    /// Entries must then be appended in the same order with the same paths
+    /// and lengths. Their metadata is taken from the index as given here, so
+    /// content types are not detected when appending, and digests are only
+    /// recorded (and checked) if they're included in the index. Entries
+    /// rejected by the [`Builder::filter`] (if set) are omitted from the index,
+    /// and the [`Builder::transform`] is applied to the paths of the rest, so
+    /// both must be configured first.
+    pub fn index_in_header(&mut self, mut entries: Vec<Entry>) -> Result<&mut Self, Error> {
+        ensure!(
+            self.stream.is_none() && self.entries.is_empty(),
+            ErrorKind::Builder,
+            "index must be provided before appending entries"
         );
 
-        Ok(Self {
-            entries: vec![],
-            writer: stream_writer,
-        })
+        if let Some(filter) = &mut self.filter {
+            entries.retain(|entry| filter(entry));
+        }
+
+        let transform = &self.transform;
+        self.entries = entries
+            .iter()
+            .filter_map(|entry| transform.apply_to_entry(entry).transpose())
+            .collect::<Result<_, _>>()?;
+        self.index_in_header = true;
+        Ok(self)
     }
 
-    /// Append an entry to the archive
+    /// Append an entry to the archive.
+    ///
+    /// The content type of regular files is detected from the first chunk of
+    /// their contents as they're written, unless detection is disabled, and
+    /// a digest of their contents is recorded in the index.
     pub fn append(&mut self, mut entry: Entry, reader: impl io::Read) -> Result<(), Error> {
-        let length = self.writer.encrypt_reader(reader)? as u64;
+        if let Some(filter) = &mut self.filter {
+            if !filter(&entry) {
+                return Ok(());
+            }
+        }
+
+        if !self.transform.is_identity() {
+            match self.transform.apply_to_entry(&entry)? {
+                Some(transformed) => entry = transformed,
+                None => return Ok(()),
+            }
+        }
+
+        if self.index_in_header {
+            let indexed = self.entries.get(self.appended).ok_or_else(|| {
+                format_err!(ErrorKind::Builder, "more entries appended than indexed")
+            })?;
+
+            ensure!(
+                indexed.path == entry.path,
+                ErrorKind::Builder,
+                "appended `{}` but expected `{}` from the index",
+                entry.path.display(),
+                indexed.path.display()
+            );
+        }
+
+        let mut reader = HeadReader::new(DigestReader::new(reader), self.chunk_size as usize);
+        let length = self.stream()?.encrypt_reader(&mut reader)? as u64;
+
+        // Only regular files have contents worth digesting
+        let digest = if entry.kind == Kind::File {
+            Some(reader.inner.digest())
+        } else {
+            None
+        };
+
+        if self.index_in_header {
+            let indexed = &self.entries[self.appended];
+
+            ensure!(
+                indexed.length == length,
+                ErrorKind::Builder,
+                "indexed length of `{}` ({}) does not match actual: {} bytes",
+                entry.path.display(),
+                indexed.length,
+                length
+            );
+
+            // The index has already been written, so digests can only be
+            // checked if they were computed in advance
+            ensure!(
+                indexed.digest.is_none() || indexed.digest == digest,
+                ErrorKind::Builder,
+                "indexed digest of `{}` does not match its contents",
+                entry.path.display()
+            );
+
+            self.appended += 1;
+            return Ok(());
+        }
+
+        if entry.kind == Kind::File {
+            if let Some(content_type) = self.content_detection.detect(&entry.path, &reader.head)? {
+                entry.attributes.content_type = content_type;
+            }
+        }
 
         // Attempt to automatically set the length (protobufs defaults to 0 for missing fields)
         if entry.length != length {
@@ -98,43 +312,57 @@ impl<W: io::Write> Builder<W> {
             }
         }
 
+        if entry.digest.is_some() && entry.digest != digest {
+            fail!(
+                ErrorKind::Argument,
+                "provided digest of `{}` does not match its contents",
+                entry.path.display()
+            );
+        }
+
+        entry.digest = digest;
         self.entries.push(entry);
         Ok(())
     }
 
     /// Finish writing the archive, adding the index and footer
     pub fn finish(mut self) -> Result<(), Error> {
-        let mut index = Index { entries: vec![] };
-
-        for entry in self.entries.into_iter() {
-            index.entries.push(entry.try_into()?);
-        }
+        ensure!(
+            !self.index_in_header || self.appended == self.entries.len(),
+            ErrorKind::Builder,
+            "only {} of {} indexed entries were appended",
+            self.appended,
+            self.entries.len()
+        );
 
-        // TODO(tarcieri): support metadata located in the header instead of the footer
-        let metadata = Metadata {
-            index: Some(index),
-            created_at: Some(Tai64n::now()),
-            username: "".to_owned(),
-            host: "".to_owned(),
-        }
-        .to_vec()?;
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.start()?,
+        };
 
-        self.writer.write_all(&metadata)?;
+        let metadata_length = if self.index_in_header {
+            0
+        } else {
+            let metadata = self.metadata()?;
+            stream.write_all(&metadata)?;
+            metadata.len() as u64
+        };
 
         // Finish writing the encrypted part of the stream, obtaining the
         // inner I/O object in order to write the plaintext footer
-        let mut writer = self.writer.finish()?;
+        let mut writer = stream.finish()?;
 
         let footer = Footer {
-            metadata_length: metadata.len() as u64,
+            metadata_length,
             signature: "".to_owned(), // TODO(tarcieri): signature support
+            segments: self.segments.iter().cloned().map(Into::into).collect(),
         }
         .to_vec()?;
 
         writer.write_all(&footer)?;
 
         ensure!(
-            footer.len() <= std::u16::MAX as usize,
+            footer.len() <= u16::MAX as usize,
             ErrorKind::Overflow,
             "oversized footer: {}-bytes",
             footer.len()
@@ -145,11 +373,216 @@ impl<W: io::Write> Builder<W> {
         writer.flush()?;
         Ok(())
     }
+
+    /// Get the encrypted stream writer, writing the header first if it
+    /// hasn't been written yet
+    fn stream(&mut self) -> Result<&mut stream::Writer<W>, Error> {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.start()?,
+        };
+
+        Ok(self.stream.insert(stream))
+    }
+
+    /// Write the plaintext header and begin the encrypted stream, writing
+    /// the metadata first if the index is stored in the header
+    fn start(&mut self) -> Result<stream::Writer<W>, Error> {
+        let (mut writer, key) = self
+            .writer
+            .take()
+            .zip(self.key.take())
+            .ok_or_else(|| format_err!(ErrorKind::Builder, "archive already started"))?;
+
+        // Write 6-byte sear archive magic identifier
+        writer.write_all(MAGIC_BYTES)?;
+
+        let metadata = if self.index_in_header {
+            Some(self.metadata()?)
+        } else {
+            None
+        };
+
+        let (metadata_location, metadata_length) = match &metadata {
+            Some(metadata) => (MetadataLocation::Header, metadata.len() as u64),
+            None => (MetadataLocation::Footer, 0),
+        };
+
+        let header = Header {
+            uuid: pad_with_newlines(&self.uuid),
+            chunk_size: self.chunk_size as u64,
+            encryption_key_fingerprint: "".to_owned(),
+            signing_key_fingerprint: "".to_owned(),
+            metadata_location: metadata_location as i32,
+            metadata_length,
+        }
+        .to_vec()?;
+
+        ensure!(
+            header.len() <= u16::MAX as usize,
+            ErrorKind::Overflow,
+            "oversized header: {}-bytes",
+            header.len()
+        );
+
+        // Write `u16` header length in little endian
+        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+
+        // Write serialized `sear.header.Header` proto
+        writer.write_all(&header)?;
+
+        let mut stream = stream::Writer::new(
+            writer,
+            key,
+            self.uuid.as_bytes(),
+            compute_aad(),
+            self.chunk_size,
+        );
+
+        stream.threads(self.threads)?;
+
+        if let Some(metadata) = metadata {
+            stream.write_all(&metadata)?;
+        }
+
+        Ok(stream)
+    }
+
+    /// Serialize the archive's metadata, including the index of entries
+    fn metadata(&self) -> Result<Vec<u8>, Error> {
+        let mut index = Index { entries: vec![] };
+
+        for entry in &self.entries {
+            index.entries.push(entry.clone().try_into()?);
+        }
+
+        Metadata {
+            index: Some(index),
+            created_at: Some(self.created_at.map(Into::into).unwrap_or_else(Tai64n::now)),
+            username: self.username.clone().unwrap_or_default(),
+            host: self.host.clone().unwrap_or_default(),
+        }
+        .to_vec()
+    }
+}
+
+impl Builder<File> {
+    /// Reopen an existing archive in order to append entries to it.
+    ///
+    /// The encrypted stream is truncated where the contents of the existing
+    /// entries end, and resumed from there under a new nonce prefix. The
+    /// index and footer are rewritten when the builder is finished, so if
+    /// appending fails partway through the archive is left unreadable. To
+    /// keep the original intact, append to a copy of it which replaces the
+    /// original once finished.
+    ///
+    /// Archives with their index stored in the header can't be appended to.
+    pub fn reopen(mut file: File, key: &kdf::Key) -> Result<Self, Error> {
+        file.seek(SeekFrom::Start(0))?;
+        let point = Archive::open(&file, key)?.append_point()?;
+        let uuid = point.header.uuid.trim().to_owned();
+        let chunk_size = ChunkSize::try_from(point.header.chunk_size)?;
+
+        let mut segments = point.segments;
+        let segment = stream::Segment::random(point.chunk_counter, &segments);
+        segments.push(segment.clone());
+
+        file.set_len(point.offset)?;
+        file.seek(SeekFrom::Start(point.offset))?;
+
+        let stream = stream::Writer::resume(
+            file,
+            key.derive_symmetric_key(&uuid),
+            compute_aad(),
+            chunk_size,
+            &segment,
+            &point.plaintext,
+        )?;
+
+        Ok(Self {
+            appended: 0,
+            chunk_size,
+            content_detection: ContentDetection::default(),
+            created_at: point.created_at,
+            entries: point.entries,
+            filter: None,
+            host: point.host,
+            index_in_header: false,
+            key: None,
+            segments,
+            stream: Some(stream),
+            threads: 1,
+            transform: Transform::default(),
+            username: point.username,
+            uuid,
+            writer: None,
+        })
+    }
+}
+
+/// Get the hostname of the local system, if it's valid UTF-8
+#[cfg(unix)]
+fn current_host() -> Option<String> {
+    nix::unistd::gethostname().ok()?.into_string().ok()
+}
+
+/// Get the hostname of the local system
+#[cfg(windows)]
+fn current_host() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Get the name of the effective user of this process, if it's valid UTF-8
+#[cfg(unix)]
+fn current_username() -> Option<String> {
+    use nix::unistd::{geteuid, User};
+
+    // Invalid UTF-8 is replaced by `nix`, so omit names which contain that
+    let name = User::from_uid(geteuid()).ok()??.name;
+    Some(name).filter(|name| !name.contains(std::char::REPLACEMENT_CHARACTER))
+}
+
+/// Get the name of the user running this process
+#[cfg(windows)]
+fn current_username() -> Option<String> {
+    std::env::var("USERNAME").ok()
+}
+
+/// Reader which retains a copy of the first bytes read through it
+struct HeadReader<R: io::Read> {
+    /// Inner reader
+    inner: R,
+
+    /// First bytes which were read
+    head: Vec<u8>,
+
+    /// Maximum number of bytes to retain
+    limit: usize,
+}
+
+impl<R: io::Read> HeadReader<R> {
+    /// Create a new reader which retains up to `limit` bytes
+    fn new(inner: R, limit: usize) -> Self {
+        Self {
+            inner,
+            head: vec![],
+            limit,
+        }
+    }
+}
+
+impl<R: io::Read> io::Read for HeadReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nbytes = self.inner.read(buf)?;
+        let remaining = self.limit - self.head.len();
+        self.head.extend_from_slice(&buf[..nbytes.min(remaining)]);
+        Ok(nbytes)
+    }
 }
 
 /// Compute Additional Authenticated Data (AAD) to use when encrypting blocks
 // TODO(tarcieri): include more data in this e.g. signature key
-fn compute_aad() -> Vec<u8> {
+pub(crate) fn compute_aad() -> Vec<u8> {
     MAGIC_BYTES.to_vec()
 }
 
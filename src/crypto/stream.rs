@@ -1,6 +1,119 @@
 //! Segmented AEAD streams
 
+pub mod decryptor;
 pub mod encryptor;
+mod pool;
+pub mod reader;
 pub mod writer;
 
-pub use self::{encryptor::Encryptor, writer::Writer};
+pub use self::{
+    decryptor::Decryptor,
+    encryptor::{Encryptor, NONCE_PREFIX_SIZE},
+    reader::Reader,
+    writer::Writer,
+};
+
+use crate::{
+    error::{Error, ErrorKind},
+    protos,
+};
+use anomaly::{ensure, format_err};
+use getrandom::getrandom;
+use std::convert::{TryFrom, TryInto};
+
+/// Segment of a STREAM which is encrypted under its own nonce prefix.
+///
+/// When a STREAM is resumed partway through (e.g. to append to an archive),
+/// the chunks which follow are rewritten with different contents. Starting a
+/// new segment with a fresh random nonce prefix ensures these never reuse a
+/// nonce from chunks which were previously written at the same positions.
+/// Chunks preceding the first segment use an all-zero nonce prefix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    /// Counter of the first chunk in this segment
+    pub first_chunk: u32,
+
+    /// Nonce prefix used to encrypt chunks in this segment
+    pub nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+}
+
+impl Segment {
+    /// Start a new segment at the given chunk with a random nonce prefix
+    /// which is distinct from those of the given existing segments
+    pub fn random(first_chunk: u32, existing: &[Segment]) -> Self {
+        loop {
+            let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+            getrandom(&mut nonce_prefix).expect("RNG failure!");
+
+            // The low bit of the last byte holds the last block flag
+            nonce_prefix[NONCE_PREFIX_SIZE - 1] &= 0xFE;
+
+            let reused = nonce_prefix == [0u8; NONCE_PREFIX_SIZE]
+                || existing.iter().any(|s| s.nonce_prefix == nonce_prefix);
+
+            if !reused {
+                return Self {
+                    first_chunk,
+                    nonce_prefix,
+                };
+            }
+        }
+    }
+
+    /// Get the nonce prefix for the chunk with the given counter, given a
+    /// STREAM's segments (in order)
+    pub fn nonce_prefix_for(segments: &[Segment], counter: u32) -> [u8; NONCE_PREFIX_SIZE] {
+        segments
+            .iter()
+            .rev()
+            .find(|segment| segment.first_chunk <= counter)
+            .map(|segment| segment.nonce_prefix)
+            .unwrap_or_default()
+    }
+
+    /// Parse and validate the segments of a STREAM from their serialized form
+    pub fn decode_all(segments: Vec<protos::footer::Segment>) -> Result<Vec<Self>, Error> {
+        let segments = segments
+            .into_iter()
+            .map(Segment::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        ensure!(
+            segments
+                .windows(2)
+                .all(|pair| pair[0].first_chunk < pair[1].first_chunk),
+            ErrorKind::Parse,
+            "STREAM segments out of order"
+        );
+
+        Ok(segments)
+    }
+}
+
+impl From<Segment> for protos::footer::Segment {
+    fn from(segment: Segment) -> Self {
+        Self {
+            first_chunk: segment.first_chunk,
+            nonce_prefix: segment.nonce_prefix.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<protos::footer::Segment> for Segment {
+    type Error = Error;
+
+    fn try_from(segment: protos::footer::Segment) -> Result<Self, Error> {
+        let nonce_prefix = segment.nonce_prefix.as_slice().try_into().map_err(|_| {
+            format_err!(
+                ErrorKind::Parse,
+                "invalid STREAM nonce prefix length: {}",
+                segment.nonce_prefix.len()
+            )
+        })?;
+
+        Ok(Self {
+            first_chunk: segment.first_chunk,
+            nonce_prefix,
+        })
+    }
+}
@@ -0,0 +1,49 @@
+//! Segmented AEAD stream decryptor
+
+use super::encryptor::{clear_last_block_flag, stream_nonce, NONCE_PREFIX_SIZE};
+use crate::{crypto::symmetric, error::Error};
+use aead::Buffer;
+
+/// A STREAM decryptor with a 32-bit counter, generalized AEAD algorithms with
+/// 96-bit nonces.
+///
+/// This corresponds to the 𝒟 stream decryptor object as defined in the paper
+/// "Online Authenticated-Encryption and its Nonce-Reuse Misuse-Resistance".
+///
+/// <https://eprint.iacr.org/2015/189.pdf>
+pub struct Decryptor {
+    /// Decryption key
+    key: symmetric::Key,
+
+    /// Leading prefix of the STREAM nonce
+    nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+}
+
+impl Decryptor {
+    /// Create a new STREAM decryptor, initialized with a given key and nonce.
+    pub fn new(key: symmetric::Key, mut nonce_prefix: [u8; NONCE_PREFIX_SIZE]) -> Self {
+        clear_last_block_flag(&mut nonce_prefix);
+        Self { key, nonce_prefix }
+    }
+
+    /// Change the nonce prefix used to decrypt subsequent messages, i.e. when
+    /// entering a new [`Segment`] of the stream
+    ///
+    /// [`Segment`]: super::Segment
+    pub fn set_nonce_prefix(&mut self, mut nonce_prefix: [u8; NONCE_PREFIX_SIZE]) {
+        clear_last_block_flag(&mut nonce_prefix);
+        self.nonce_prefix = nonce_prefix;
+    }
+
+    /// Decrypt a message located at the given position in the stream in-place
+    pub fn decrypt_in_place(
+        &self,
+        counter: u32,
+        last_block: bool,
+        associated_data: &[u8],
+        buffer: &mut impl Buffer,
+    ) -> Result<(), Error> {
+        let nonce = stream_nonce(&self.nonce_prefix, counter, last_block);
+        self.key.decrypt_in_place(&nonce, associated_data, buffer)
+    }
+}
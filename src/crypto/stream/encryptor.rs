@@ -7,7 +7,7 @@ use crate::{
 use aead::Buffer;
 
 /// Size of a nonce prefix (nonce size less a 32-bit counter)
-const NONCE_PREFIX_SIZE: usize = NONCE_SIZE - 4;
+pub const NONCE_PREFIX_SIZE: usize = NONCE_SIZE - 4;
 
 /// Index of the byte where we store the "last block" flag.
 ///
@@ -38,7 +38,7 @@ impl Encryptor {
     pub fn new(key: symmetric::Key, mut nonce_prefix: [u8; NONCE_PREFIX_SIZE]) -> Self {
         // We store the "last block flag" in the last byte of the nonce prefix.
         // This clears the bit from the nonce prefix.
-        nonce_prefix[LAST_BLOCK_FLAG_BYTE] &= 0xFE;
+        clear_last_block_flag(&mut nonce_prefix);
 
         Self { key, nonce_prefix }
     }
@@ -51,21 +51,30 @@ impl Encryptor {
         associated_data: &[u8],
         buffer: &mut impl Buffer,
     ) -> Result<(), Error> {
-        let nonce = self.stream_nonce(counter, last_block);
+        let nonce = stream_nonce(&self.nonce_prefix, counter, last_block);
         self.key.encrypt_in_place(&nonce, associated_data, buffer)
     }
+}
 
-    /// Compute a STREAM nonce based on the given prefix, block counter, and
-    /// last block flag.
-    fn stream_nonce(&self, counter: u32, last_block: bool) -> [u8; NONCE_SIZE] {
-        let mut nonce = [0u8; NONCE_SIZE];
-        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.nonce_prefix);
-        nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&counter.to_le_bytes());
+/// Clear the "last block flag" bit from a nonce prefix.
+pub(super) fn clear_last_block_flag(nonce_prefix: &mut [u8; NONCE_PREFIX_SIZE]) {
+    nonce_prefix[LAST_BLOCK_FLAG_BYTE] &= 0xFE;
+}
 
-        if last_block {
-            nonce[LAST_BLOCK_FLAG_BYTE] |= 1;
-        }
+/// Compute a STREAM nonce based on the given prefix, block counter, and
+/// last block flag.
+pub(super) fn stream_nonce(
+    nonce_prefix: &[u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+    last_block: bool,
+) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(nonce_prefix);
+    nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&counter.to_le_bytes());
 
-        nonce
+    if last_block {
+        nonce[LAST_BLOCK_FLAG_BYTE] |= 1;
     }
+
+    nonce
 }
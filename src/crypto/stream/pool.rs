@@ -0,0 +1,179 @@
+//! Pool of threads which encrypt STREAM chunks concurrently
+
+use super::{writer::MAX_THREADS, Encryptor};
+use crate::error::{Error, ErrorKind};
+use anomaly::{ensure, format_err};
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// Chunk to be encrypted, along with its counter
+type Job = (u32, Vec<u8>);
+
+/// Encrypted chunk (or the error encrypting it), along with its counter
+type Output = (u32, Result<Vec<u8>, Error>);
+
+/// Pool of worker threads which encrypt full (i.e. not last) chunks of a
+/// STREAM concurrently.
+///
+/// Chunks are submitted in order by the thread writing the STREAM, which
+/// can keep reading ahead while earlier chunks are being encrypted. Encrypted
+/// chunks are written out in order as they become available.
+pub(super) struct Pool {
+    /// Queue of chunks waiting to be encrypted (dropped to stop the workers)
+    jobs: Option<mpsc::Sender<Job>>,
+
+    /// Chunks which have been encrypted
+    outputs: mpsc::Receiver<Output>,
+
+    /// Encrypted chunks which are waiting for earlier ones to be written
+    ready: BTreeMap<u32, Vec<u8>>,
+
+    /// Counter of the next chunk to be written
+    next_counter: u32,
+
+    /// Number of chunks submitted but not yet written
+    in_flight: usize,
+
+    /// Maximum number of chunks in flight at once
+    max_in_flight: usize,
+
+    /// Worker threads
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl Pool {
+    /// Start a pool of the given number of threads (at most
+    /// [`MAX_THREADS`]), encrypting chunks beginning with the given counter
+    pub fn new(
+        threads: usize,
+        encryptor: Arc<Encryptor>,
+        aad: Arc<[u8]>,
+        first_counter: u32,
+    ) -> Result<Self, Error> {
+        ensure!(
+            threads > 0 && threads <= MAX_THREADS,
+            ErrorKind::Argument,
+            "invalid number of STREAM encryption threads: {}",
+            threads
+        );
+
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (output_sender, outputs) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let mut workers = Vec::with_capacity(threads);
+
+        for _ in 0..threads {
+            let job_receiver = Arc::clone(&job_receiver);
+            let output_sender = output_sender.clone();
+            let encryptor = Arc::clone(&encryptor);
+            let aad = Arc::clone(&aad);
+
+            let worker = thread::Builder::new()
+                .name("sear-encrypt".to_owned())
+                .spawn(move || loop {
+                    // Release the lock before encrypting, so other workers
+                    // can pick up jobs meanwhile
+                    let job = job_receiver.lock().unwrap().recv();
+
+                    let (counter, mut buffer) = match job {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+
+                    let result = encryptor
+                        .encrypt_in_place(counter, false, &aad, &mut buffer)
+                        .map(|()| buffer);
+
+                    if output_sender.send((counter, result)).is_err() {
+                        return;
+                    }
+                })?;
+
+            workers.push(worker);
+        }
+
+        Ok(Self {
+            jobs: Some(jobs),
+            outputs,
+            ready: BTreeMap::new(),
+            next_counter: first_counter,
+            in_flight: 0,
+            // Keep the workers busy while completed chunks are written
+            max_in_flight: threads * 2,
+            workers,
+        })
+    }
+
+    /// Submit a chunk to be encrypted, writing any chunks which are ready in
+    /// the meantime. Blocks while the maximum number of chunks are in flight.
+    pub fn submit(
+        &mut self,
+        counter: u32,
+        chunk: Vec<u8>,
+        io: &mut impl io::Write,
+    ) -> Result<(), Error> {
+        while self.in_flight >= self.max_in_flight {
+            self.receive(io)?;
+        }
+
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send((counter, chunk))
+            .map_err(|_| format_err!(ErrorKind::Crypto, "STREAM encryption thread exited"))?;
+
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Wait for every chunk which has been submitted to be encrypted and
+    /// written
+    pub fn flush(&mut self, io: &mut impl io::Write) -> Result<(), Error> {
+        while self.in_flight > 0 {
+            self.receive(io)?;
+        }
+
+        Ok(())
+    }
+
+    /// Wait for a chunk to be encrypted, then write out every chunk which
+    /// is next in order
+    fn receive(&mut self, io: &mut impl io::Write) -> Result<(), Error> {
+        let (counter, result) = self
+            .outputs
+            .recv()
+            .map_err(|_| format_err!(ErrorKind::Crypto, "STREAM encryption thread exited"))?;
+
+        ensure!(
+            counter >= self.next_counter,
+            ErrorKind::Crypto,
+            "STREAM chunk {} encrypted twice",
+            counter
+        );
+
+        self.ready.insert(counter, result?);
+
+        while let Some(chunk) = self.ready.remove(&self.next_counter) {
+            io.write_all(&chunk)?;
+            self.next_counter = self.next_counter.checked_add(1).unwrap();
+            self.in_flight -= 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Closing the queue makes the workers exit once it's drained
+        self.jobs = None;
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Segmented AEAD STREAM reader
+
+use super::writer::ChunkSize;
+use crate::{
+    crypto::{stream, symmetric},
+    error::{Error, ErrorKind},
+};
+use anomaly::{ensure, format_err};
+use std::{
+    convert::TryFrom,
+    io::{self, SeekFrom},
+};
+
+/// Segmented AEAD STREAM reader
+pub struct Reader<R: io::Read> {
+    /// Additional associated data
+    aad: Vec<u8>,
+
+    /// Internal buffer containing the most recently decrypted chunk
+    buffer: Vec<u8>,
+
+    /// Position within our internal buffer
+    buffer_pos: usize,
+
+    /// Counter of the next chunk to be read from the underlying I/O object
+    chunk_counter: u32,
+
+    /// Size of chunks to read
+    chunk_size: ChunkSize,
+
+    /// Total length of the STREAM ciphertext (including tags), if known.
+    ///
+    /// If unknown, the underlying I/O object is read sequentially until EOF.
+    ciphertext_length: Option<u64>,
+
+    /// STREAM decryptor
+    decryptor: stream::Decryptor,
+
+    /// Has the last chunk been read (when the ciphertext length is unknown)?
+    finished: bool,
+
+    /// Underlying I/O object to read from
+    io: R,
+
+    /// Byte read ahead of the current chunk to determine whether it's the
+    /// last one (when the ciphertext length is unknown)
+    peeked: Option<u8>,
+
+    /// Segments of the STREAM encrypted under their own nonce prefixes
+    segments: Vec<stream::Segment>,
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Create a new STREAM reader.
+    ///
+    /// The underlying I/O object is expected to be positioned at the start of
+    /// a STREAM ciphertext which is `ciphertext_length` bytes long.
+    pub fn new(
+        io: R,
+        key: symmetric::Key,
+        aad: impl Into<Vec<u8>>,
+        chunk_size: ChunkSize,
+        ciphertext_length: u64,
+    ) -> Self {
+        Self::init(io, key, aad.into(), chunk_size, Some(ciphertext_length))
+    }
+
+    /// Create a new STREAM reader for a ciphertext of unknown length which
+    /// extends until the underlying I/O object reaches EOF.
+    ///
+    /// Whether each chunk is the last one is determined by reading ahead by
+    /// one byte, so the underlying I/O object need not support seeking.
+    pub fn new_sequential(
+        io: R,
+        key: symmetric::Key,
+        aad: impl Into<Vec<u8>>,
+        chunk_size: ChunkSize,
+    ) -> Self {
+        Self::init(io, key, aad.into(), chunk_size, None)
+    }
+
+    /// Initialize a STREAM reader
+    fn init(
+        io: R,
+        key: symmetric::Key,
+        aad: Vec<u8>,
+        chunk_size: ChunkSize,
+        ciphertext_length: Option<u64>,
+    ) -> Self {
+        // TODO(tarcieri): use randomized or derived (e.g. via HKDF) nonce?
+        let nonce_prefix = Default::default();
+
+        Self {
+            aad,
+            buffer: Vec::with_capacity(chunk_size as usize + symmetric::TAG_SIZE),
+            buffer_pos: 0,
+            chunk_counter: 0,
+            chunk_size,
+            ciphertext_length,
+            decryptor: stream::Decryptor::new(key, nonce_prefix),
+            finished: false,
+            io,
+            peeked: None,
+            segments: vec![],
+        }
+    }
+
+    /// Set the segments of this STREAM which are encrypted under their own
+    /// nonce prefixes (see [`stream::Segment`])
+    pub fn with_segments(mut self, segments: Vec<stream::Segment>) -> Self {
+        self.segments = segments;
+        self
+    }
+
+    /// Total length of the plaintext contained in this STREAM, if known
+    pub fn plaintext_length(&self) -> Result<Option<u64>, Error> {
+        let ciphertext_length = match self.ciphertext_length {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+
+        let chunk_size = self.encrypted_chunk_size();
+        let chunk_count = (ciphertext_length + chunk_size - 1) / chunk_size;
+
+        // Every chunk (including the last one) holds at least one byte of
+        // plaintext along with its MAC tag
+        let last_chunk_size = ciphertext_length - chunk_count.saturating_sub(1) * chunk_size;
+
+        ensure!(
+            chunk_count == 0 || last_chunk_size > symmetric::TAG_SIZE as u64,
+            ErrorKind::Parse,
+            "truncated stream"
+        );
+
+        let plaintext_length = ciphertext_length
+            .checked_sub(chunk_count * symmetric::TAG_SIZE as u64)
+            .ok_or_else(|| format_err!(ErrorKind::Parse, "truncated stream"))?;
+
+        Ok(Some(plaintext_length))
+    }
+
+    /// Current position within the plaintext of this STREAM
+    pub fn position(&self) -> u64 {
+        match self.chunk_counter.checked_sub(1) {
+            Some(chunk) => u64::from(chunk) * self.chunk_size as u64 + self.buffer_pos as u64,
+            None => 0,
+        }
+    }
+
+    /// Size of a chunk including its MAC tag
+    fn encrypted_chunk_size(&self) -> u64 {
+        (self.chunk_size as usize + symmetric::TAG_SIZE) as u64
+    }
+
+    /// Read the next chunk from the underlying I/O object and decrypt it into
+    /// the internal buffer. Returns `false` if the end of the STREAM has been
+    /// reached.
+    fn decrypt_chunk(&mut self) -> Result<bool, Error> {
+        let result = self.try_decrypt_chunk();
+
+        // Never leave partially read or undecrypted data in the buffer
+        if result.is_err() {
+            self.buffer.clear();
+            self.buffer_pos = 0;
+        }
+
+        result
+    }
+
+    /// Attempt to read and decrypt the next chunk into the internal buffer
+    fn try_decrypt_chunk(&mut self) -> Result<bool, Error> {
+        let last_block = match self.ciphertext_length {
+            Some(ciphertext_length) => self.read_chunk(ciphertext_length)?,
+            None => self.read_chunk_sequential()?,
+        };
+
+        let last_block = match last_block {
+            Some(last_block) => last_block,
+            None => {
+                self.buffer_pos = self.buffer.len();
+                return Ok(false);
+            }
+        };
+
+        if !self.segments.is_empty() {
+            let nonce_prefix =
+                stream::Segment::nonce_prefix_for(&self.segments, self.chunk_counter);
+            self.decryptor.set_nonce_prefix(nonce_prefix);
+        }
+
+        self.decryptor.decrypt_in_place(
+            self.chunk_counter,
+            last_block,
+            &self.aad,
+            &mut self.buffer,
+        )?;
+
+        self.chunk_counter = self
+            .chunk_counter
+            .checked_add(1)
+            .ok_or_else(|| format_err!(ErrorKind::Crypto, "STREAM chunk counter overflowed"))?;
+
+        self.buffer_pos = 0;
+        Ok(true)
+    }
+
+    /// Read the next encrypted chunk of a STREAM of known length into the
+    /// internal buffer, returning whether it's the last chunk, or `None` if
+    /// the end of the STREAM has been reached
+    fn read_chunk(&mut self, ciphertext_length: u64) -> Result<Option<bool>, Error> {
+        let encrypted_chunk_size = self.encrypted_chunk_size();
+        let offset = u64::from(self.chunk_counter) * encrypted_chunk_size;
+
+        if offset >= ciphertext_length {
+            return Ok(None);
+        }
+
+        let remaining = ciphertext_length - offset;
+        let last_block = remaining <= encrypted_chunk_size;
+        let nbytes = if last_block {
+            remaining
+        } else {
+            encrypted_chunk_size
+        };
+
+        self.buffer.resize(nbytes as usize, 0);
+        self.io.read_exact(&mut self.buffer)?;
+        Ok(Some(last_block))
+    }
+
+    /// Read the next encrypted chunk of a STREAM of unknown length into the
+    /// internal buffer, returning whether it's the last chunk, or `None` if
+    /// the end of the STREAM has been reached
+    fn read_chunk_sequential(&mut self) -> Result<Option<bool>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let encrypted_chunk_size = self.encrypted_chunk_size() as usize;
+        self.buffer.clear();
+        self.buffer.extend(self.peeked.take());
+
+        let mut filled = self.buffer.len();
+        self.buffer.resize(encrypted_chunk_size, 0);
+
+        while filled < encrypted_chunk_size {
+            match self.io.read(&mut self.buffer[filled..]) {
+                Ok(0) => break,
+                Ok(nbytes) => filled += nbytes,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        self.buffer.truncate(filled);
+
+        ensure!(
+            filled > symmetric::TAG_SIZE,
+            ErrorKind::Crypto,
+            "truncated STREAM chunk"
+        );
+
+        // A full-sized chunk is the last one only if it's followed by EOF
+        let last_block = if filled < encrypted_chunk_size {
+            true
+        } else {
+            let mut byte = [0u8];
+
+            loop {
+                match self.io.read(&mut byte) {
+                    Ok(0) => break true,
+                    Ok(_) => {
+                        self.peeked = Some(byte[0]);
+                        break false;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        };
+
+        self.finished = last_block;
+        Ok(Some(last_block))
+    }
+}
+
+impl<R: io::Read + io::Seek> Reader<R> {
+    /// Seek to the given position within the plaintext of this STREAM
+    pub fn seek(&mut self, position: u64) -> Result<(), Error> {
+        let plaintext_length = self.plaintext_length()?.ok_or_else(|| {
+            format_err!(
+                ErrorKind::Argument,
+                "can't seek within a STREAM of unknown length"
+            )
+        })?;
+
+        ensure!(
+            position <= plaintext_length,
+            ErrorKind::Argument,
+            "seek position {} is past the end of the stream",
+            position
+        );
+
+        let chunk_size = self.chunk_size as u64;
+        let target_chunk = u32::try_from(position / chunk_size)
+            .map_err(|_| format_err!(ErrorKind::Overflow, "seek position too large"))?;
+        let target_pos = (position % chunk_size) as usize;
+
+        // Avoid re-reading the chunk which is currently buffered
+        if self.chunk_counter > 0
+            && self.chunk_counter - 1 == target_chunk
+            && target_pos <= self.buffer.len()
+        {
+            self.buffer_pos = target_pos;
+            return Ok(());
+        }
+
+        // Compute the current and desired offsets within the ciphertext
+        let encrypted_chunk_size = self.encrypted_chunk_size();
+        let current_offset = (u64::from(self.chunk_counter) * encrypted_chunk_size)
+            .min(self.ciphertext_length.unwrap_or_default());
+        let target_offset = u64::from(target_chunk) * encrypted_chunk_size;
+
+        self.io.seek(SeekFrom::Current(
+            target_offset as i64 - current_offset as i64,
+        ))?;
+        self.chunk_counter = target_chunk;
+
+        if self.decrypt_chunk()? {
+            self.buffer_pos = target_pos;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: io::Read> io::Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer_pos == self.buffer.len() {
+            let more = self
+                .decrypt_chunk()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            if !more {
+                return Ok(0);
+            }
+        }
+
+        let nbytes = buf.len().min(self.buffer.len() - self.buffer_pos);
+        let buf_end = self.buffer_pos + nbytes;
+        buf[..nbytes].copy_from_slice(&self.buffer[self.buffer_pos..buf_end]);
+        self.buffer_pos = buf_end;
+
+        Ok(nbytes)
+    }
+}
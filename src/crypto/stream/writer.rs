@@ -1,26 +1,37 @@
 //! Segmented AEAD STREAM writer
 
+use super::pool::Pool;
 use crate::{
     crypto::{stream, symmetric},
     error::{Error, ErrorKind},
 };
-use anomaly::format_err;
-use std::io;
+use anomaly::{ensure, fail, format_err};
+use std::{convert::TryFrom, io, mem, sync::Arc};
+
+/// Maximum number of threads a STREAM can be encrypted on
+pub const MAX_THREADS: usize = 256;
 
 /// Valid chunk sizes
 #[repr(usize)]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, PartialOrd, Ord)]
 pub enum ChunkSize {
     /// 1 kibibyte (1024 bytes)
     Kib1 = 1024,
 
     /// 128 kibibytes (131,072 bytes)
+    #[default]
     Kib128 = 131_072,
 }
 
-impl Default for ChunkSize {
-    fn default() -> Self {
-        ChunkSize::Kib128
+impl TryFrom<u64> for ChunkSize {
+    type Error = Error;
+
+    fn try_from(chunk_size: u64) -> Result<Self, Error> {
+        match chunk_size {
+            1024 => Ok(ChunkSize::Kib1),
+            131_072 => Ok(ChunkSize::Kib128),
+            _ => fail!(ErrorKind::Parse, "invalid chunk size: {}", chunk_size),
+        }
     }
 }
 
@@ -42,10 +53,13 @@ pub struct Writer<W: io::Write> {
     chunk_size: ChunkSize,
 
     /// STREAM encryptor
-    encryptor: stream::Encryptor,
+    encryptor: Arc<stream::Encryptor>,
 
     /// Underlying I/O object to write to
     io: W,
+
+    /// Threads encrypting full chunks (if encrypting concurrently)
+    pool: Option<Pool>,
 }
 
 impl<W: io::Write> Writer<W> {
@@ -73,11 +87,84 @@ impl<W: io::Write> Writer<W> {
             buffer_pos: 0,
             chunk_counter: 0,
             chunk_size,
-            encryptor: stream::Encryptor::new(key, nonce_prefix),
+            encryptor: Arc::new(stream::Encryptor::new(key, nonce_prefix)),
             io,
+            pool: None,
         }
     }
 
+    /// Resume writing a STREAM partway through, starting a new segment.
+    ///
+    /// The underlying I/O object is expected to be positioned where the
+    /// segment's first chunk begins, and `plaintext` is the beginning of that
+    /// chunk's contents (which must be shorter than a full chunk).
+    pub fn resume(
+        io: W,
+        key: symmetric::Key,
+        aad: impl Into<Vec<u8>>,
+        chunk_size: ChunkSize,
+        segment: &stream::Segment,
+        plaintext: &[u8],
+    ) -> Result<Self, Error> {
+        ensure!(
+            plaintext.len() < chunk_size as usize,
+            ErrorKind::Crypto,
+            "resumed STREAM chunk is oversized: {} bytes",
+            plaintext.len()
+        );
+
+        let mut buffer = Vec::with_capacity(chunk_size as usize + symmetric::TAG_SIZE);
+        buffer.extend_from_slice(plaintext);
+        buffer.resize(chunk_size as usize, 0);
+
+        Ok(Self {
+            aad: aad.into(),
+            buffer,
+            buffer_pos: plaintext.len(),
+            chunk_counter: segment.first_chunk,
+            chunk_size,
+            encryptor: Arc::new(stream::Encryptor::new(key, segment.nonce_prefix)),
+            io,
+            pool: None,
+        })
+    }
+
+    /// Encrypt full chunks concurrently on the given number of threads,
+    /// while still writing them in order. With a single thread (the
+    /// default), chunks are encrypted on the calling thread.
+    pub fn threads(&mut self, threads: usize) -> Result<&mut Self, Error> {
+        ensure!(
+            threads > 0,
+            ErrorKind::Argument,
+            "STREAM must be encrypted with at least one thread"
+        );
+
+        ensure!(
+            threads <= MAX_THREADS,
+            ErrorKind::Argument,
+            "STREAM can't be encrypted with more than {} threads (got {})",
+            MAX_THREADS,
+            threads
+        );
+
+        if let Some(pool) = &mut self.pool {
+            pool.flush(&mut self.io)?;
+        }
+
+        self.pool = if threads > 1 {
+            Some(Pool::new(
+                threads,
+                Arc::clone(&self.encryptor),
+                Arc::from(self.aad.as_slice()),
+                self.chunk_counter,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(self)
+    }
+
     /// Encrypt the given input, filling the internal buffer and then
     /// encrypting a fixed-sized chunk using our STREAM writer
     pub fn encrypt_reader(&mut self, mut reader: impl io::Read) -> Result<usize, Error> {
@@ -86,7 +173,18 @@ impl<W: io::Write> Writer<W> {
 
         loop {
             if self.buffer_pos == self.chunk_size as usize {
+                // Only encrypt a full buffer once more data is known to
+                // follow, as it might otherwise need to be the last block
+                let mut byte = [0u8];
+
+                if reader.read(&mut byte)? == 0 {
+                    break;
+                }
+
                 self.encrypt_chunk()?;
+                self.buffer[0] = byte[0];
+                self.buffer_pos = 1;
+                length = length.checked_add(1).unwrap();
             }
 
             let nbytes = reader.read(&mut self.buffer[self.buffer_pos..])?;
@@ -147,6 +245,11 @@ impl<W: io::Write> Writer<W> {
         // We always lazily encrypt, so otherwise the buffer should never be empty
         assert_ne!(self.buffer_pos, 0, "unexpected empty buffer");
 
+        // Chunks still being encrypted must be written ahead of the last one
+        if let Some(pool) = &mut self.pool {
+            pool.flush(&mut self.io)?;
+        }
+
         // Otherwise encrypt the remaining data in the buffer as the last block
         self.buffer.truncate(self.buffer_pos);
         self.encryptor
@@ -163,18 +266,33 @@ impl<W: io::Write> Writer<W> {
             "attempted to encrypt buffer when it isn't full!"
         );
 
-        self.encryptor
-            .encrypt_in_place(self.chunk_counter, false, &self.aad, &mut self.buffer)?;
+        let counter = self.chunk_counter;
 
         self.chunk_counter = self
             .chunk_counter
             .checked_add(1)
             .ok_or_else(|| format_err!(ErrorKind::Crypto, "STREAM chunk counter overflowed"))?;
 
-        self.io.write_all(&self.buffer)?;
+        match &mut self.pool {
+            Some(pool) => {
+                // Hand the chunk off to the pool and continue with a new buffer
+                let mut buffer = Vec::with_capacity(self.chunk_size as usize + symmetric::TAG_SIZE);
+                buffer.resize(self.chunk_size as usize, 0);
+
+                let chunk = mem::replace(&mut self.buffer, buffer);
+                pool.submit(counter, chunk, &mut self.io)?;
+            }
+            None => {
+                self.encryptor
+                    .encrypt_in_place(counter, false, &self.aad, &mut self.buffer)?;
+
+                self.io.write_all(&self.buffer)?;
+
+                // Remove the MAC tag from the end of the buffer
+                self.buffer.truncate(self.chunk_size as usize);
+            }
+        }
 
-        // Remove the MAC tag from the end of the buffer
-        self.buffer.truncate(self.chunk_size as usize);
         self.buffer_pos = 0;
 
         Ok(())
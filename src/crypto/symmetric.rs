@@ -38,6 +38,23 @@ impl Key {
         }
         .map_err(|_| ErrorKind::Crypto.into())
     }
+
+    /// Decrypt the given buffer containing a ciphertext message in-place,
+    /// removing the authentication tag from the end of the buffer.
+    pub fn decrypt_in_place(
+        &self,
+        nonce: &[u8; NONCE_SIZE],
+        associated_data: &[u8],
+        buffer: &mut impl Buffer,
+    ) -> Result<(), Error> {
+        let nonce = GenericArray::from(*nonce);
+
+        match self {
+            Key::Aes256Gcm(key) => key.decrypt_in_place(&nonce, associated_data, buffer),
+            Key::ChaCha20Poly1305(key) => key.decrypt_in_place(&nonce, associated_data, buffer),
+        }
+        .map_err(|_| ErrorKind::Crypto.into())
+    }
 }
 
 impl Debug for Key {
@@ -0,0 +1,248 @@
+//! Differences between entries, e.g. between an archive and the files on the
+//! local filesystem, or between two archives
+
+use crate::entry::{Entry, Kind, Owner};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Size of the buffers used when comparing contents
+const BUFFER_SIZE: usize = 65536;
+
+/// Ways in which two versions of an entry can differ
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Difference {
+    /// The newer version is missing (i.e. the entry was removed)
+    Missing,
+
+    /// The older version is missing (i.e. the entry was added)
+    Added,
+
+    /// The older version is a tombstone, but the newer one still exists
+    /// (i.e. the entry should have been deleted)
+    Present,
+
+    /// Kind of entry (e.g. a regular file versus a directory)
+    Kind,
+
+    /// Target of a link
+    Target,
+
+    /// Length of the file
+    Length,
+
+    /// Contents of the file
+    Contents,
+
+    /// Permission bits of the file's mode
+    Mode,
+
+    /// POSIX ACLs or SELinux labels
+    AccessControl,
+
+    /// Owning user or group
+    Owner,
+
+    /// Date when the file was last modified
+    ModifiedAt,
+}
+
+impl Difference {
+    /// Get a short, stable identifier for this difference (for use in
+    /// machine-readable output)
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Difference::Missing => "missing",
+            Difference::Added => "added",
+            Difference::Present => "present",
+            Difference::Kind => "kind",
+            Difference::Target => "target",
+            Difference::Length => "size",
+            Difference::Contents => "contents",
+            Difference::Mode => "mode",
+            Difference::AccessControl => "acl",
+            Difference::Owner => "owner",
+            Difference::ModifiedAt => "mtime",
+        }
+    }
+
+    /// Get a human-readable description of this difference ala `tar -d`
+    pub fn description(self) -> &'static str {
+        match self {
+            Difference::Missing => "Missing",
+            Difference::Added => "Added",
+            Difference::Present => "Should not exist",
+            Difference::Kind => "File type differs",
+            Difference::Target => "Link target differs",
+            Difference::Length => "Size differs",
+            Difference::Contents => "Contents differ",
+            Difference::Mode => "Mode differs",
+            Difference::AccessControl => "ACLs or SELinux labels differ",
+            Difference::Owner => "Uid/gid differs",
+            Difference::ModifiedAt => "Mod time differs",
+        }
+    }
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Compare the metadata of two versions of an entry.
+///
+/// Contents are compared by digest when both versions have one. Attributes
+/// which are unknown for either version (e.g. modification times) aren't
+/// considered to differ. If the kinds of the entries differ, nothing else is
+/// compared. A tombstone compared against an entry which isn't one is
+/// reported as [`Difference::Present`] (or [`Difference::Missing`] the other
+/// way around), rather than as a different kind of entry.
+pub fn compare(old: &Entry, new: &Entry) -> Vec<Difference> {
+    let mut differences = vec![];
+
+    match (&old.kind, &new.kind) {
+        (Kind::Deleted, Kind::Deleted) => return vec![],
+        (Kind::Deleted, _) => return vec![Difference::Present],
+        (_, Kind::Deleted) => return vec![Difference::Missing],
+        (
+            Kind::Link {
+                symbolic: old_symbolic,
+                target: old_target,
+            },
+            Kind::Link {
+                symbolic: new_symbolic,
+                target: new_target,
+            },
+        ) => {
+            if old_symbolic != new_symbolic {
+                return vec![Difference::Kind];
+            }
+
+            if old_target != new_target {
+                differences.push(Difference::Target);
+            }
+        }
+        (old_kind, new_kind) if old_kind != new_kind => return vec![Difference::Kind],
+        _ => (),
+    }
+
+    if old.length != new.length {
+        differences.push(Difference::Length);
+    } else if old.digest.is_some() && new.digest.is_some() && old.digest != new.digest {
+        differences.push(Difference::Contents);
+    }
+
+    if old.permissions.mode.permissions() != new.permissions.mode.permissions() {
+        differences.push(Difference::Mode);
+    }
+
+    if old.permissions.posix_acls != new.permissions.posix_acls
+        || old.permissions.selinux_labels != new.permissions.selinux_labels
+    {
+        differences.push(Difference::AccessControl);
+    }
+
+    if owner_differs(&old.owner, &new.owner) {
+        differences.push(Difference::Owner);
+    }
+
+    if let (Some(old_time), Some(new_time)) =
+        (old.attributes.modified_at, new.attributes.modified_at)
+    {
+        if old_time != new_time {
+            differences.push(Difference::ModifiedAt);
+        }
+    }
+
+    differences
+}
+
+/// Compare the indexes of two archives, matching up entries by path.
+///
+/// Only the latest version of each path is compared, and paths whose latest
+/// version is a tombstone are considered absent. Paths which differ are
+/// returned in order, along with their differences.
+pub fn compare_indexes(old: &[Entry], new: &[Entry]) -> Vec<(PathBuf, Vec<Difference>)> {
+    let old = latest_versions(old);
+    let new = latest_versions(new);
+
+    let paths = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    let mut changes = vec![];
+
+    for path in paths {
+        let differences = match (old.get(path), new.get(path)) {
+            (Some(old_entry), Some(new_entry)) => compare(old_entry, new_entry),
+            (Some(_), None) => vec![Difference::Missing],
+            (None, Some(_)) => vec![Difference::Added],
+            (None, None) => unreachable!(),
+        };
+
+        if !differences.is_empty() {
+            changes.push(((*path).to_owned(), differences));
+        }
+    }
+
+    changes
+}
+
+/// Compare two readers byte-for-byte, returning whether their contents are
+/// identical
+pub fn same_contents(mut a: impl io::Read, mut b: impl io::Read) -> io::Result<bool> {
+    let mut a_buf = vec![0u8; BUFFER_SIZE];
+    let mut b_buf = vec![0u8; BUFFER_SIZE];
+
+    loop {
+        let nbytes = read_full(&mut a, &mut a_buf)?;
+
+        if read_full(&mut b, &mut b_buf[..nbytes])? != nbytes || a_buf[..nbytes] != b_buf[..nbytes]
+        {
+            return Ok(false);
+        }
+
+        if nbytes < BUFFER_SIZE {
+            // `a` is exhausted, so `b` must be too
+            return Ok(read_full(&mut b, &mut b_buf[..1])? == 0);
+        }
+    }
+}
+
+/// Do two owners differ? Numerical IDs are compared if both are known,
+/// otherwise names are compared.
+fn owner_differs(a: &Owner, b: &Owner) -> bool {
+    match (&a.id, &b.id) {
+        (Some(a_id), Some(b_id)) => a_id != b_id,
+        _ => a.name != b.name,
+    }
+}
+
+/// Get the latest version of each path in an index, omitting tombstones
+fn latest_versions(entries: &[Entry]) -> BTreeMap<&Path, &Entry> {
+    let mut latest = BTreeMap::new();
+
+    for entry in entries {
+        latest.insert(entry.path.as_path(), entry);
+    }
+
+    latest.retain(|_, entry| entry.kind != Kind::Deleted);
+    latest
+}
+
+/// Fill as much of a buffer as possible, stopping short only at EOF
+fn read_full(reader: &mut impl io::Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(nbytes) => filled += nbytes,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
@@ -5,10 +5,11 @@
 //! Protobuf-defined types exclusively for serialization.
 
 pub mod attributes;
+pub mod digest;
 pub mod owner;
 pub mod permissions;
 
-pub use self::{attributes::Attributes, owner::Owner, permissions::Permissions};
+pub use self::{attributes::Attributes, digest::Digest, owner::Owner, permissions::Permissions};
 
 use crate::{
     error::{Error, ErrorKind},
@@ -18,8 +19,12 @@ use anomaly::{fail, format_err};
 use std::{
     convert::{TryFrom, TryInto},
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
+use tai64::TAI64N;
+
+/// Media type of tombstone entries, which have no contents
+const TOMBSTONE_CONTENT_TYPE: &str = "inode/x-deleted";
 
 /// Entry within a .sear archive file
 #[derive(Clone, Debug, PartialEq)]
@@ -30,6 +35,9 @@ pub struct Entry {
     /// Length of the file in bytes
     pub length: u64,
 
+    /// SHA-256 digest of the file's contents (computed when it's archived)
+    pub digest: Option<Digest>,
+
     /// File owner
     pub owner: Owner,
 
@@ -46,26 +54,30 @@ pub struct Entry {
 impl Entry {
     /// Create an [`Entry`] for a file on the local filesystem
     pub fn for_file(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let path = path.as_ref().to_owned();
+        // Stat the file only once, deriving everything else from the result
+        let metadata = fs::symlink_metadata(path.as_ref())?;
+        Self::for_metadata(path, &metadata)
+    }
 
-        if path.to_str().is_none() {
-            fail!(
-                ErrorKind::Path,
-                "invalid UTF-8 in path: `{}`",
-                path.display()
-            );
-        }
+    /// Create an [`Entry`] for a file on the local filesystem from its
+    /// (`lstat`-style) metadata
+    pub fn for_metadata(path: impl AsRef<Path>, metadata: &fs::Metadata) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        let owner = Owner::from(metadata);
+        let kind = Kind::for_file(&path, metadata)?;
 
-        let metadata = fs::symlink_metadata(&path)?;
-        let length = metadata.len();
-        let owner = Owner::from(&metadata);
-        let kind = Kind::for_file(&path)?;
-        let permissions = Permissions::for_file(&path)?;
-        let attributes = Attributes::for_file(&path)?;
+        // Only regular files have contents stored in the archive
+        let length = match kind {
+            Kind::File => metadata.len(),
+            Kind::Directory | Kind::Link { .. } | Kind::Deleted => 0,
+        };
+        let permissions = Permissions::for_file(&path, metadata)?;
+        let attributes = Attributes::for_metadata(metadata)?;
 
         Ok(Self {
             path,
             length,
+            digest: None,
             owner,
             permissions,
             attributes,
@@ -73,6 +85,60 @@ impl Entry {
         })
     }
 
+    /// Create an [`Entry`] for a regular file whose contents are read from a
+    /// stream (e.g. stdin) rather than the local filesystem.
+    ///
+    /// The entry is owned by the current user, has `0644` permissions, and
+    /// was last modified now. Its length is unknown until its contents have
+    /// been read, so it's left as zero.
+    pub fn for_stream(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.as_ref().to_owned(),
+            length: 0,
+            digest: None,
+            owner: Owner::current(),
+            permissions: Permissions {
+                mode: permissions::Mode::new(Some(permissions::mode::FileType::Regular), 0o644)?,
+                posix_acls: vec![],
+                selinux_labels: vec![],
+            },
+            attributes: Attributes {
+                created_at: None,
+                modified_at: Some(TAI64N::now()),
+                accessed_at: None,
+                changed_at: None,
+                content_type: mime::APPLICATION_OCTET_STREAM,
+                xattr: vec![],
+            },
+            kind: Kind::File,
+        })
+    }
+
+    /// Create a tombstone [`Entry`] recording that the file at the given path
+    /// has been deleted (e.g. since a previous incremental archive)
+    pub fn tombstone(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self {
+            path: path.as_ref().to_owned(),
+            length: 0,
+            digest: None,
+            owner: Owner::default(),
+            permissions: Permissions {
+                mode: permissions::Mode::new(None, 0)?,
+                posix_acls: vec![],
+                selinux_labels: vec![],
+            },
+            attributes: Attributes {
+                created_at: None,
+                modified_at: None,
+                accessed_at: None,
+                changed_at: None,
+                content_type: TOMBSTONE_CONTENT_TYPE.parse()?,
+                xattr: vec![],
+            },
+            kind: Kind::Deleted,
+        })
+    }
+
     /// Format the file length in a human-friendly way: with length suffixes
     /// (e.g. KiB, MiB, GiB) ala the `-h` flag in `ls` and `df`
     pub fn length_formatted(&self) -> String {
@@ -111,22 +177,50 @@ impl Entry {
     }
 }
 
+/// Normalize a path for storage in an archive.
+///
+/// Leading `/` (and on Windows, drive prefixes) are removed unless
+/// `preserve_absolute` is set, `.` components are dropped, and repeated
+/// separators are collapsed. Paths containing `..` are rejected.
+pub fn normalize_path(path: &Path, preserve_absolute: bool) -> Result<PathBuf, Error> {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => {
+                if preserve_absolute {
+                    normalized.push(component);
+                }
+            }
+            Component::CurDir => (),
+            Component::ParentDir => fail!(
+                ErrorKind::Path,
+                "refusing to archive path containing `..`: `{}`",
+                path.display()
+            ),
+            Component::Normal(name) => normalized.push(name),
+        }
+    }
+
+    Ok(normalized)
+}
+
 impl TryFrom<Entry> for protos::Entry {
     type Error = Error;
 
     fn try_from(entry: Entry) -> Result<Self, Error> {
-        let path_str = entry.path.to_str().ok_or_else(|| {
-            format_err!(
-                ErrorKind::Path,
-                "invalid UTF-8 in path: `{}`",
-                entry.path.display()
-            )
-        })?;
+        let (path, path_bytes) = encode_path(&entry.path)?;
 
         Ok(Self {
-            path: path_str.to_owned(),
+            path,
+            path_bytes,
             length: entry.length,
-            owner: Some(entry.owner.into()),
+            sha256: entry
+                .digest
+                .map(|digest| digest.as_bytes().to_vec())
+                .unwrap_or_default(),
+            owner_id: entry.owner.id.map(Into::into),
+            owner_name: entry.owner.name.map(Into::into),
             permissions: Some(entry.permissions.into()),
             attributes: Some(entry.attributes.into()),
             kind: Some(entry.kind.try_into()?),
@@ -134,13 +228,70 @@ impl TryFrom<Entry> for protos::Entry {
     }
 }
 
+impl TryFrom<protos::Entry> for Entry {
+    type Error = Error;
+
+    fn try_from(entry: protos::Entry) -> Result<Self, Error> {
+        let path = decode_path(entry.path, entry.path_bytes)?;
+
+        let digest = if entry.sha256.is_empty() {
+            None
+        } else {
+            Some(Digest::try_from(entry.sha256.as_slice())?)
+        };
+
+        let owner = Owner {
+            id: entry.owner_id.map(Into::into),
+            name: entry.owner_name.map(TryInto::try_into).transpose()?,
+        };
+
+        let permissions = entry
+            .permissions
+            .ok_or_else(|| {
+                format_err!(
+                    ErrorKind::Parse,
+                    "missing permissions: `{}`",
+                    path.display()
+                )
+            })?
+            .try_into()?;
+
+        let attributes = entry
+            .attributes
+            .ok_or_else(|| {
+                format_err!(ErrorKind::Parse, "missing attributes: `{}`", path.display())
+            })?
+            .try_into()?;
+
+        let kind = entry
+            .kind
+            .ok_or_else(|| {
+                format_err!(ErrorKind::Parse, "missing entry kind: `{}`", path.display())
+            })?
+            .try_into()?;
+
+        Ok(Self {
+            path,
+            length: entry.length,
+            digest,
+            owner,
+            permissions,
+            attributes,
+            kind,
+        })
+    }
+}
+
 /// Kinds of entries
-// TODO(tarcieri): character devices, block devices, directories, FIFOs, etc.
+// TODO(tarcieri): character devices, block devices, FIFOs, etc.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Kind {
     /// Regular files
     File,
 
+    /// Directories
+    Directory,
+
     /// Hard or symbolic links
     Link {
         /// Is this a symbolic (as opposed to hard) link?
@@ -149,12 +300,16 @@ pub enum Kind {
         /// Target path the link is pointing to
         target: PathBuf,
     },
+
+    /// Tombstones recording that a file was deleted
+    Deleted,
 }
 
 impl Kind {
-    /// Get the kind of file on the local filesystem
-    pub fn for_file(path: &impl AsRef<Path>) -> Result<Self, Error> {
-        let file_type = fs::symlink_metadata(path)?.file_type();
+    /// Get the kind of file on the local filesystem from its path and
+    /// (`lstat`-style) metadata
+    pub fn for_file(path: &impl AsRef<Path>, metadata: &fs::Metadata) -> Result<Self, Error> {
+        let file_type = metadata.file_type();
 
         if file_type.is_symlink() {
             // TODO(tarcieri): hard links?
@@ -165,9 +320,14 @@ impl Kind {
             })
         } else if file_type.is_file() {
             Ok(Kind::File)
+        } else if file_type.is_dir() {
+            Ok(Kind::Directory)
         } else {
-            // TODO(tarcieri): handle directories
-            fail!(ErrorKind::Path, "directories unsupported")
+            fail!(
+                ErrorKind::Path,
+                "unsupported file type: `{}`",
+                path.as_ref().display()
+            )
         }
     }
 }
@@ -178,20 +338,85 @@ impl TryFrom<Kind> for protos::entry::Kind {
     fn try_from(kind: Kind) -> Result<Self, Error> {
         Ok(match kind {
             Kind::File => protos::entry::Kind::File(protos::entry::FileEntry {}),
+            Kind::Directory => protos::entry::Kind::Directory(protos::entry::DirectoryEntry {}),
             Kind::Link { symbolic, target } => {
-                let target_str = target.to_str().ok_or_else(|| {
-                    format_err!(
-                        ErrorKind::Path,
-                        "invalid UTF-8 in path: `{}`",
-                        target.display()
-                    )
-                })?;
+                let (target, target_bytes) = encode_path(&target)?;
 
                 protos::entry::Kind::Link(protos::entry::LinkEntry {
                     symbolic,
-                    target: target_str.to_owned(),
+                    target,
+                    target_bytes,
                 })
             }
+            Kind::Deleted => protos::entry::Kind::Deleted(protos::entry::DeletedEntry {}),
         })
     }
 }
+
+impl TryFrom<protos::entry::Kind> for Kind {
+    type Error = Error;
+
+    fn try_from(kind: protos::entry::Kind) -> Result<Self, Error> {
+        Ok(match kind {
+            protos::entry::Kind::File(_) => Kind::File,
+            protos::entry::Kind::Directory(_) => Kind::Directory,
+            protos::entry::Kind::Link(link) => Kind::Link {
+                symbolic: link.symbolic,
+                target: decode_path(link.target, link.target_bytes)?,
+            },
+            protos::entry::Kind::Deleted(_) => Kind::Deleted,
+        })
+    }
+}
+
+/// Encode a path for serialization, returning it as a string if it's valid
+/// UTF-8, or otherwise as raw bytes
+#[cfg(unix)]
+pub fn encode_path(path: &Path) -> Result<(String, Vec<u8>), Error> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(match path.to_str() {
+        Some(s) => (s.to_owned(), vec![]),
+        None => (String::new(), path.as_os_str().as_bytes().to_vec()),
+    })
+}
+
+/// Encode a path for serialization, returning it as a string if it's valid
+/// UTF-8, or otherwise as raw bytes
+#[cfg(windows)]
+pub fn encode_path(path: &Path) -> Result<(String, Vec<u8>), Error> {
+    match path.to_str() {
+        Some(s) => Ok((s.to_owned(), vec![])),
+        None => fail!(
+            ErrorKind::Path,
+            "invalid Unicode in path: `{}`",
+            path.display()
+        ),
+    }
+}
+
+/// Decode a path serialized by [`encode_path`], preferring raw bytes if
+/// present
+#[cfg(unix)]
+pub(crate) fn decode_path(string: String, bytes: Vec<u8>) -> Result<PathBuf, Error> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    if bytes.is_empty() {
+        Ok(string.into())
+    } else {
+        Ok(OsString::from_vec(bytes).into())
+    }
+}
+
+/// Decode a path serialized by [`encode_path`], preferring raw bytes if
+/// present
+#[cfg(windows)]
+pub(crate) fn decode_path(string: String, bytes: Vec<u8>) -> Result<PathBuf, Error> {
+    if bytes.is_empty() {
+        Ok(string.into())
+    } else {
+        String::from_utf8(bytes)
+            .map(Into::into)
+            .map_err(|_| format_err!(ErrorKind::Path, "non-UTF-8 path unsupported").into())
+    }
+}
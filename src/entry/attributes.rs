@@ -4,25 +4,33 @@ use crate::{
     error::{Error, ErrorKind},
     protos,
 };
-use anomaly::ensure;
+use anomaly::{ensure, fail};
 use mime::Mime;
 use std::{
+    convert::{TryFrom, TryInto},
     fmt::{self, Display},
-    fs,
+    fs::Metadata,
     path::Path,
     str::FromStr,
+    time::SystemTime,
 };
 use tai64::TAI64N;
 
 /// File attributes
 #[derive(Clone, Debug, PartialEq)]
 pub struct Attributes {
-    /// Date when the file was created
+    /// Date when the file was created (i.e. its birth time, if available)
     pub created_at: Option<TAI64N>,
 
     /// Date when the file was last modified
     pub modified_at: Option<TAI64N>,
 
+    /// Date when the file was last accessed
+    pub accessed_at: Option<TAI64N>,
+
+    /// Date when the file's status (i.e. its metadata) last changed
+    pub changed_at: Option<TAI64N>,
+
     /// Media type (a.k.a. MIME type)
     pub content_type: Mime,
 
@@ -31,19 +39,27 @@ pub struct Attributes {
 }
 
 impl Attributes {
-    /// Get the attributes for a file on the local filesystem
-    pub fn for_file(path: &impl AsRef<Path>) -> Result<Self, Error> {
-        let metadata = fs::symlink_metadata(path)?;
-
-        // Tolerate platforms where creation time/modification time are unavailable
+    /// Get the attributes for a file on the local filesystem from its
+    /// (`lstat`-style) metadata.
+    ///
+    /// The content type of regular files is set to `application/octet-stream`
+    /// as a placeholder: see [`ContentDetection`] for detecting it from the
+    /// file's contents as they're archived.
+    pub fn for_metadata(metadata: &Metadata) -> Result<Self, Error> {
+        // Tolerate platforms and filesystems where timestamps are unavailable
         let created_at = metadata.created().ok().map(Into::into);
         let modified_at = metadata.modified().ok().map(Into::into);
+        let accessed_at = metadata.accessed().ok().map(Into::into);
+        let changed_at = status_changed(metadata).map(Into::into);
+
+        let file_type = metadata.file_type();
 
-        let content_type = if metadata.file_type().is_symlink() {
+        let content_type = if file_type.is_symlink() {
             "inode/symlink".parse()?
+        } else if file_type.is_dir() {
+            "inode/directory".parse()?
         } else {
-            // TODO(tarcieri): support for disabling automatic MIME type detection
-            tree_magic::from_filepath(path.as_ref()).parse()?
+            mime::APPLICATION_OCTET_STREAM
         };
 
         // TODO(tarcieri): xattr support
@@ -52,6 +68,8 @@ impl Attributes {
         Ok(Self {
             created_at,
             modified_at,
+            accessed_at,
+            changed_at,
             content_type,
             xattr,
         })
@@ -63,12 +81,115 @@ impl From<Attributes> for protos::entry::Attributes {
         Self {
             created_at: attrs.created_at.map(Into::into),
             modified_at: attrs.modified_at.map(Into::into),
+            accessed_at: attrs.accessed_at.map(Into::into),
+            changed_at: attrs.changed_at.map(Into::into),
             content_type: attrs.content_type.to_string(),
             xattr: attrs.xattr.iter().map(ToString::to_string).collect(),
         }
     }
 }
 
+impl TryFrom<protos::entry::Attributes> for Attributes {
+    type Error = Error;
+
+    fn try_from(attrs: protos::entry::Attributes) -> Result<Self, Error> {
+        Ok(Self {
+            created_at: attrs
+                .created_at
+                .as_ref()
+                .map(TryInto::try_into)
+                .transpose()?,
+            modified_at: attrs
+                .modified_at
+                .as_ref()
+                .map(TryInto::try_into)
+                .transpose()?,
+            accessed_at: attrs
+                .accessed_at
+                .as_ref()
+                .map(TryInto::try_into)
+                .transpose()?,
+            changed_at: attrs
+                .changed_at
+                .as_ref()
+                .map(TryInto::try_into)
+                .transpose()?,
+            content_type: attrs.content_type.parse()?,
+            xattr: attrs
+                .xattr
+                .iter()
+                .map(|xattr| xattr.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Strategies for detecting the content type (a.k.a. MIME type) of files
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ContentDetection {
+    /// Sniff the content type from the first chunk of a file's contents
+    #[default]
+    Magic,
+
+    /// Map file extensions to content types
+    Extension,
+
+    /// Don't detect content types, leaving them as-is
+    Disabled,
+}
+
+impl ContentDetection {
+    /// Detect the content type of a file from its path and the first bytes
+    /// of its contents, returning `None` if detection is disabled
+    pub fn detect(self, path: &Path, head: &[u8]) -> Result<Option<Mime>, Error> {
+        Ok(match self {
+            ContentDetection::Magic => Some(tree_magic::from_u8(head).parse()?),
+            ContentDetection::Extension => {
+                Some(mime_guess::from_path(path).first_or_octet_stream())
+            }
+            ContentDetection::Disabled => None,
+        })
+    }
+}
+
+impl FromStr for ContentDetection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "magic" => Ok(ContentDetection::Magic),
+            "extension" => Ok(ContentDetection::Extension),
+            "none" => Ok(ContentDetection::Disabled),
+            _ => fail!(
+                ErrorKind::Parse,
+                "unknown content type detection: `{}` (expected magic, extension, or none)",
+                s
+            ),
+        }
+    }
+}
+
+/// Get the time when a file's status (i.e. its metadata) last changed
+#[cfg(unix)]
+fn status_changed(metadata: &Metadata) -> Option<SystemTime> {
+    use std::{os::unix::fs::MetadataExt, time::Duration};
+
+    let nanos = Duration::from_nanos(u64::try_from(metadata.ctime_nsec()).ok()?);
+
+    match u64::try_from(metadata.ctime()) {
+        Ok(secs) => SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(secs) + nanos),
+        Err(_) => SystemTime::UNIX_EPOCH
+            .checked_sub(Duration::from_secs(metadata.ctime().unsigned_abs()))?
+            .checked_add(nanos),
+    }
+}
+
+/// Get the time when a file's status (i.e. its metadata) last changed
+#[cfg(windows)]
+fn status_changed(_metadata: &Metadata) -> Option<SystemTime> {
+    None
+}
+
 /// Extended attributes for modern Unix filesystems.
 ///
 /// See the `attr(5)` manpage for more information.
@@ -0,0 +1,90 @@
+//! Digests of the contents of entries
+
+use crate::error::{Error, ErrorKind};
+use anomaly::format_err;
+use sha2::{Digest as _, Sha256};
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt::{self, Display},
+    io,
+};
+
+/// SHA-256 digest of the contents of an entry
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Digest([u8; Digest::SIZE]);
+
+impl Digest {
+    /// Size of a digest in bytes
+    pub const SIZE: usize = 32;
+
+    /// Compute the digest of everything read from the given reader
+    pub fn for_reader(reader: impl io::Read) -> io::Result<Self> {
+        let mut reader = DigestReader::new(reader);
+        io::copy(&mut reader, &mut io::sink())?;
+        Ok(reader.digest())
+    }
+
+    /// Get the raw bytes of this digest
+    pub fn as_bytes(&self) -> &[u8; Digest::SIZE] {
+        &self.0
+    }
+}
+
+impl From<[u8; Digest::SIZE]> for Digest {
+    fn from(bytes: [u8; Digest::SIZE]) -> Self {
+        Digest(bytes)
+    }
+}
+
+impl TryFrom<&[u8]> for Digest {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Error> {
+        bytes.try_into().map(Digest).map_err(|_| {
+            format_err!(ErrorKind::Parse, "invalid digest length: {}", bytes.len()).into()
+        })
+    }
+}
+
+impl Display for Digest {
+    /// Format this digest as lower-case hexadecimal
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reader which computes the digest of everything read through it
+pub struct DigestReader<R: io::Read> {
+    /// Inner reader
+    inner: R,
+
+    /// Hash of everything read so far
+    hasher: Sha256,
+}
+
+impl<R: io::Read> DigestReader<R> {
+    /// Create a new reader which computes a digest of the inner reader
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Get the digest of everything read so far
+    pub fn digest(&self) -> Digest {
+        Digest(self.hasher.clone().finalize().into())
+    }
+}
+
+impl<R: io::Read> io::Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let nbytes = self.inner.read(buf)?;
+        self.hasher.update(&buf[..nbytes]);
+        Ok(nbytes)
+    }
+}
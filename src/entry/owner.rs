@@ -6,34 +6,219 @@ use crate::{
 };
 use anomaly::{ensure, fail};
 use std::{
+    convert::TryFrom,
     fmt::{self, Display},
     fs::Metadata,
     str::FromStr,
 };
 
-/// File owner: user/group who owns a particular file
-#[derive(Clone, Debug, PartialEq)]
-pub enum Owner {
+#[cfg(unix)]
+use nix::unistd::{Gid, Group, Uid, User};
+
+#[cfg(unix)]
+use std::{cell::RefCell, collections::HashMap};
+
+#[cfg(unix)]
+thread_local! {
+    /// Cache of lookups in the system's passwd and group databases
+    static NAME_CACHE: RefCell<NameCache> = RefCell::new(NameCache::default());
+}
+
+/// File owner: user/group who owns a particular file.
+///
+/// Both the numerical IDs and the corresponding names can be recorded, since
+/// the mapping between them often differs between hosts. If neither is
+/// present, the owner is unspecified.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Owner {
     /// Numerical IDs
-    Id {
-        /// User ID
-        uid: u32,
+    pub id: Option<OwnerId>,
 
-        /// Group ID
-        gid: u32,
-    },
+    /// Names of the owning user and group
+    pub name: Option<OwnerName>,
+}
 
-    /// Named owner
-    Name {
-        /// Name of the owning user
-        username: Name,
+/// Numerical IDs of a file's owning user and group
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct OwnerId {
+    /// User ID
+    pub uid: u32,
+
+    /// Group ID
+    pub gid: u32,
+}
 
-        /// Name of the owning group
-        groupname: Name,
-    },
+/// Names of a file's owning user and group.
+///
+/// Either name may be absent if it couldn't be resolved when archiving.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OwnerName {
+    /// Name of the owning user
+    pub username: Option<Name>,
 
-    /// Unspecified owner
-    Unspecified,
+    /// Name of the owning group
+    pub groupname: Option<Name>,
+}
+
+impl Owner {
+    /// Get the owner of files created by the current process (i.e. its
+    /// effective user and group)
+    #[cfg(unix)]
+    pub fn current() -> Self {
+        use nix::unistd::{getegid, geteuid};
+
+        Self::for_id(OwnerId {
+            uid: geteuid().as_raw(),
+            gid: getegid().as_raw(),
+        })
+    }
+
+    /// Get the owner of files created by the current process
+    #[cfg(windows)]
+    pub fn current() -> Self {
+        Owner::default()
+    }
+
+    /// Get the owner with the given numerical IDs, along with their names
+    /// from the local passwd and group databases
+    #[cfg(unix)]
+    fn for_id(id: OwnerId) -> Self {
+        // Names which are missing from the local databases or which aren't
+        // valid (e.g. non-UTF-8) are omitted, leaving only the numerical ID
+        let (username, groupname) = NAME_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            (cache.username(id.uid), cache.groupname(id.gid))
+        });
+
+        let name = if username.is_some() || groupname.is_some() {
+            Some(OwnerName {
+                username,
+                groupname,
+            })
+        } else {
+            None
+        };
+
+        Owner { id: Some(id), name }
+    }
+
+    /// Is the owner of this file unspecified?
+    pub fn is_unspecified(&self) -> bool {
+        self.id.is_none() && self.name.is_none()
+    }
+
+    /// Resolve the UID and GID this owner maps to on the local system.
+    ///
+    /// Names are preferred, falling back to the recorded numerical IDs when a
+    /// name is absent or unknown locally. If `numeric_owner` is set, names are
+    /// ignored and only the numerical IDs are used.
+    #[cfg(unix)]
+    pub fn resolve(&self, numeric_owner: bool) -> (Option<u32>, Option<u32>) {
+        let names = self.name.as_ref().filter(|_| !numeric_owner);
+
+        NAME_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+
+            let uid = names
+                .and_then(|n| n.username.as_ref())
+                .and_then(|username| cache.uid(username))
+                .or_else(|| self.id.map(|id| id.uid));
+
+            let gid = names
+                .and_then(|n| n.groupname.as_ref())
+                .and_then(|groupname| cache.gid(groupname))
+                .or_else(|| self.id.map(|id| id.gid));
+
+            (uid, gid)
+        })
+    }
+
+    /// Resolve the UID and GID this owner maps to on the local system.
+    #[cfg(windows)]
+    pub fn resolve(&self, _numeric_owner: bool) -> (Option<u32>, Option<u32>) {
+        (None, None)
+    }
+}
+
+/// Cache of lookups in the system's passwd and group databases, in both
+/// directions. Failed lookups are cached as `None`.
+#[cfg(unix)]
+#[derive(Default)]
+struct NameCache {
+    /// Names of users by UID
+    usernames: HashMap<u32, Option<Name>>,
+
+    /// Names of groups by GID
+    groupnames: HashMap<u32, Option<Name>>,
+
+    /// UIDs of users by name
+    uids: HashMap<Name, Option<u32>>,
+
+    /// GIDs of groups by name
+    gids: HashMap<Name, Option<u32>>,
+}
+
+#[cfg(unix)]
+impl NameCache {
+    /// Look up the name of the user with the given UID. Names which are
+    /// missing or which aren't valid are omitted.
+    fn username(&mut self, uid: u32) -> Option<Name> {
+        self.usernames
+            .entry(uid)
+            .or_insert_with(|| {
+                let user = User::from_uid(Uid::from_raw(uid)).ok()??;
+                user.name.parse().ok()
+            })
+            .clone()
+    }
+
+    /// Look up the name of the group with the given GID. Names which are
+    /// missing or which aren't valid are omitted.
+    fn groupname(&mut self, gid: u32) -> Option<Name> {
+        self.groupnames
+            .entry(gid)
+            .or_insert_with(|| {
+                let group = Group::from_gid(Gid::from_raw(gid)).ok()??;
+                group.name.parse().ok()
+            })
+            .clone()
+    }
+
+    /// Look up the UID of the user with the given name
+    fn uid(&mut self, username: &Name) -> Option<u32> {
+        *self
+            .uids
+            .entry(username.clone())
+            .or_insert_with(|| Some(User::from_name(username.as_ref()).ok()??.uid.as_raw()))
+    }
+
+    /// Look up the GID of the group with the given name
+    fn gid(&mut self, groupname: &Name) -> Option<u32> {
+        *self
+            .gids
+            .entry(groupname.clone())
+            .or_insert_with(|| Some(Group::from_name(groupname.as_ref()).ok()??.gid.as_raw()))
+    }
+}
+
+/// Display owners as `user/group` ala `tar -tv`, falling back to numerical
+/// IDs for names which are unknown
+impl Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name.clone().unwrap_or_default();
+
+        match (name.username, self.id) {
+            (Some(username), _) => write!(f, "{}", username)?,
+            (None, Some(id)) => write!(f, "{}", id.uid)?,
+            (None, None) => f.write_str("?")?,
+        }
+
+        match (name.groupname, self.id) {
+            (Some(groupname), _) => write!(f, "/{}", groupname),
+            (None, Some(id)) => write!(f, "/{}", id.gid),
+            (None, None) => f.write_str("/?"),
+        }
+    }
 }
 
 #[cfg(unix)]
@@ -41,46 +226,73 @@ impl From<&Metadata> for Owner {
     fn from(metadata: &Metadata) -> Self {
         use std::os::unix::fs::MetadataExt;
 
-        Owner::Id {
+        Owner::for_id(OwnerId {
             uid: metadata.uid(),
             gid: metadata.gid(),
-        }
+        })
     }
 }
 
 #[cfg(windows)]
 impl From<&Metadata> for Owner {
     fn from(_metadata: &Metadata) -> Self {
-        Owner::Unspecified
+        Owner::default()
     }
 }
 
-impl From<Owner> for protos::entry::Owner {
-    fn from(owner: Owner) -> Self {
-        match owner {
-            Owner::Id { uid, gid } => {
-                let owner_id = protos::entry::OwnerId { uid, gid };
-                protos::entry::Owner::Id(owner_id)
-            }
-            Owner::Name {
-                username,
-                groupname,
-            } => {
-                let owner_name = protos::entry::OwnerName {
-                    username: username.to_string(),
-                    groupname: groupname.to_string(),
-                };
-                protos::entry::Owner::Name(owner_name)
-            }
-            Owner::Unspecified => {
-                protos::entry::Owner::Unspecified(protos::entry::OwnerUnspecified {})
-            }
+impl From<OwnerId> for protos::entry::OwnerId {
+    fn from(id: OwnerId) -> Self {
+        Self {
+            uid: id.uid,
+            gid: id.gid,
+        }
+    }
+}
+
+impl From<protos::entry::OwnerId> for OwnerId {
+    fn from(id: protos::entry::OwnerId) -> Self {
+        Self {
+            uid: id.uid,
+            gid: id.gid,
         }
     }
 }
 
-/// User or group names
-#[derive(Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+impl From<OwnerName> for protos::entry::OwnerName {
+    fn from(name: OwnerName) -> Self {
+        Self {
+            username: name.username.map(|n| n.to_string()).unwrap_or_default(),
+            groupname: name.groupname.map(|n| n.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<protos::entry::OwnerName> for OwnerName {
+    type Error = Error;
+
+    fn try_from(name: protos::entry::OwnerName) -> Result<Self, Error> {
+        // Empty strings denote names which weren't resolved when archiving
+        let parse_name = |s: String| -> Result<Option<Name>, Error> {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                s.parse().map(Some)
+            }
+        };
+
+        Ok(Self {
+            username: parse_name(name.username)?,
+            groupname: parse_name(name.groupname)?,
+        })
+    }
+}
+
+/// User or group names.
+///
+/// These follow the POSIX portable character set (letters, digits, `.`, `_`,
+/// and `-`, but not starting with `-`), additionally allowing a trailing `$`
+/// as used for e.g. Samba machine accounts.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub struct Name(String);
 
 impl AsRef<str> for Name {
@@ -101,14 +313,13 @@ impl FromStr for Name {
     fn from_str(s: &str) -> Result<Self, Error> {
         ensure!(!s.is_empty(), ErrorKind::Parse, "names cannot be empty");
 
-        for (i, c) in s.chars().enumerate() {
+        let last = s.len() - 1;
+
+        for (i, c) in s.char_indices() {
             match c {
-                'a'..='z' => continue,
-                '0'..='9' | '-' => {
-                    if i != 0 {
-                        continue;
-                    }
-                }
+                'a'..='z' | 'A'..='Z' | '_' => continue,
+                '0'..='9' | '.' | '-' if i != 0 => continue,
+                '$' if i == last && i != 0 => continue,
                 _ => (),
             }
 
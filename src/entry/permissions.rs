@@ -1,19 +1,26 @@
 //! File permissions
 
+pub mod mode;
+
+pub use self::mode::Mode;
+
 use crate::{
     error::{Error, ErrorKind},
     protos,
 };
-use anomaly::ensure;
+use anomaly::{ensure, format_err};
 use std::{
     convert::TryFrom,
     fmt::{self, Display},
+    fs::Metadata,
+    io,
     path::Path,
-    str::FromStr,
+    process::Command,
+    str::{self, FromStr},
 };
 
 #[cfg(unix)]
-use std::{fs, os::unix::fs::MetadataExt};
+use std::os::unix::fs::MetadataExt;
 
 /// File permissions
 #[derive(Clone, Debug, PartialEq)]
@@ -29,16 +36,16 @@ pub struct Permissions {
 }
 
 impl Permissions {
-    /// Get the attributes for a file on the local filesystem
+    /// Get the permissions for a file on the local filesystem from its path
+    /// and (`lstat`-style) metadata
     #[cfg(unix)]
-    pub fn for_file(path: &impl AsRef<Path>) -> Result<Self, Error> {
-        let mode = Mode::try_from(fs::symlink_metadata(path)?.mode())?;
+    pub fn for_file(path: &impl AsRef<Path>, metadata: &Metadata) -> Result<Self, Error> {
+        let mode = Mode::try_from(metadata.mode())?;
 
         // TODO(tarcieri): POSIX ACL support
         let posix_acls = vec![];
 
-        // TODO(tarcieri): SELinux label support
-        let selinux_labels = vec![];
+        let selinux_labels = SELinuxLabel::for_file(path)?.into_iter().collect();
 
         Ok(Permissions {
             mode,
@@ -47,12 +54,13 @@ impl Permissions {
         })
     }
 
-    /// Get the attributes for a file on the local filesystem
+    /// Get the permissions for a file on the local filesystem from its path
+    /// and (`lstat`-style) metadata
     #[cfg(windows)]
-    pub fn for_file(_path: &impl AsRef<Path>) -> Result<Self, Error> {
+    pub fn for_file(_path: &impl AsRef<Path>, _metadata: &Metadata) -> Result<Self, Error> {
         Ok(Permissions {
             // Use a default Unix mode value on Windows
-            mode: Mode(0o644),
+            mode: Mode::new(Some(mode::FileType::Regular), 0o644)?,
             posix_acls: vec![],
             selinux_labels: vec![],
         })
@@ -73,37 +81,23 @@ impl From<Permissions> for protos::entry::Permissions {
     }
 }
 
-/// Unix file mode (i.e. permissions)
-///
-/// See the `chmod(1)` manpage for more information.
-// TODO(tarcieri): use a bitfield for representing this?
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct Mode(u32);
-
-impl Display for Mode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:o}", self)
-    }
-}
-
-impl fmt::Octal for Mode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Octal::fmt(&self.0, f)
-    }
-}
-
-impl From<Mode> for u32 {
-    fn from(mode: Mode) -> u32 {
-        mode.0
-    }
-}
-
-impl TryFrom<u32> for Mode {
+impl TryFrom<protos::entry::Permissions> for Permissions {
     type Error = Error;
 
-    fn try_from(mode: u32) -> Result<Self, Error> {
-        // TODO(tarcieri): validate mode
-        Ok(Mode(mode))
+    fn try_from(perms: protos::entry::Permissions) -> Result<Self, Error> {
+        Ok(Self {
+            mode: Mode::try_from(perms.mode)?,
+            posix_acls: perms
+                .posix_acls
+                .iter()
+                .map(|acl| acl.parse())
+                .collect::<Result<_, _>>()?,
+            selinux_labels: perms
+                .selinux_labels
+                .iter()
+                .map(|label| label.parse())
+                .collect::<Result<_, _>>()?,
+        })
     }
 }
 
@@ -142,12 +136,100 @@ impl FromStr for PosixAcl {
 
 /// SELinux file labels: define the SELinux context of a file.
 ///
-/// All SELinux policy decisions are based on these labels.
+/// All SELinux policy decisions are based on these labels, which are
+/// structured as `user:role:type:level`. The level (a.k.a. MLS range) is
+/// optional on systems which don't use multi-level security.
 ///
 /// See the `semanage-fcontext(8)` manpage for more information.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SELinuxLabel(String);
 
+impl SELinuxLabel {
+    /// Name of the extended attribute in which SELinux stores file labels
+    pub const XATTR_NAME: &'static str = "security.selinux";
+
+    /// Get the SELinux label of a file on the local filesystem (if it has one)
+    #[cfg(unix)]
+    pub fn for_file(path: &impl AsRef<Path>) -> Result<Option<Self>, Error> {
+        let label = match xattr::get(path, Self::XATTR_NAME) {
+            Ok(Some(label)) => label,
+            Ok(None) => return Ok(None),
+            // Tolerate filesystems and kernels without xattr support
+            Err(e) if is_unsupported(&e) => return Ok(None),
+            Err(e) => {
+                return Err(format_err!(
+                    ErrorKind::Io,
+                    "couldn't get SELinux label of `{}`: {}",
+                    path.as_ref().display(),
+                    e
+                )
+                .into())
+            }
+        };
+
+        // The kernel includes a trailing NUL byte in the attribute's value
+        let label = label.strip_suffix(&[0]).unwrap_or(&label);
+
+        let label_str = str::from_utf8(label)
+            .map_err(|_| format_err!(ErrorKind::Parse, "invalid UTF-8 in SELinux label"))?;
+
+        label_str.parse().map(Some)
+    }
+
+    /// Get the SELinux label of a file on the local filesystem (if it has one)
+    #[cfg(windows)]
+    pub fn for_file(_path: &impl AsRef<Path>) -> Result<Option<Self>, Error> {
+        Ok(None)
+    }
+
+    /// Apply this label to a file on the local filesystem
+    #[cfg(unix)]
+    pub fn apply(&self, path: &impl AsRef<Path>) -> Result<(), Error> {
+        xattr::set(path, Self::XATTR_NAME, self.0.as_bytes()).map_err(|e| {
+            format_err!(
+                ErrorKind::Io,
+                "couldn't set SELinux label on `{}`: {}",
+                path.as_ref().display(),
+                e
+            )
+            .into()
+        })
+    }
+
+    /// Apply this label to a file on the local filesystem
+    #[cfg(windows)]
+    pub fn apply(&self, _path: &impl AsRef<Path>) -> Result<(), Error> {
+        Err(format_err!(ErrorKind::Argument, "SELinux labels unsupported on Windows").into())
+    }
+
+    /// Relabel the given files on the local filesystem, along with the
+    /// contents of any directories among them, according to the system's
+    /// default file context rules (see the `restorecon(8)` manpage).
+    ///
+    /// This runs `restorecon` once for all of the files. Like it, symbolic
+    /// links aren't followed.
+    pub fn restore_default<P: AsRef<Path>>(paths: &[P]) -> Result<(), Error> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let status = Command::new("restorecon")
+            .args(["-R", "-F", "--"])
+            .args(paths.iter().map(AsRef::as_ref))
+            .status()
+            .map_err(|e| format_err!(ErrorKind::Io, "couldn't run restorecon: {}", e))?;
+
+        ensure!(
+            status.success(),
+            ErrorKind::Io,
+            "restorecon failed ({})",
+            status
+        );
+
+        Ok(())
+    }
+}
+
 impl AsRef<str> for SELinuxLabel {
     fn as_ref(&self) -> &str {
         &self.0
@@ -170,7 +252,93 @@ impl FromStr for SELinuxLabel {
             "SELinux labels cannot be empty"
         );
 
-        // TODO(tarcieri): validate SELinux file labels are well-structured
+        let mut components = s.splitn(4, ':');
+
+        for component in &["user", "role", "type"] {
+            let identifier = components.next().unwrap_or_default();
+
+            ensure!(
+                is_selinux_identifier(identifier),
+                ErrorKind::Parse,
+                "invalid SELinux {} in label: `{}`",
+                component,
+                s
+            );
+        }
+
+        if let Some(level) = components.next() {
+            ensure!(
+                is_selinux_range(level),
+                ErrorKind::Parse,
+                "invalid SELinux level in label: `{}`",
+                s
+            );
+        }
+
         Ok(SELinuxLabel(s.to_owned()))
     }
 }
+
+/// Policies for restoring SELinux labels when extracting files
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SELinuxRestore {
+    /// Don't touch labels: files get whatever label policy assigns on creation
+    #[default]
+    Skip,
+
+    /// Apply the labels recorded in the archive
+    Archived,
+
+    /// Relabel files using the system's default file context rules, once
+    /// extraction has finished
+    Default,
+}
+
+/// Is the given error due to extended attributes being unsupported (e.g. by
+/// the filesystem)? These are distinct errors on some platforms.
+#[cfg(unix)]
+fn is_unsupported(err: &io::Error) -> bool {
+    use nix::libc::{ENOTSUP, EOPNOTSUPP};
+
+    let code = err.raw_os_error();
+    code == Some(ENOTSUP) || code == Some(EOPNOTSUPP)
+}
+
+/// Is the given string a valid SELinux user, role, or type identifier?
+fn is_selinux_identifier(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+}
+
+/// Is the given string a valid SELinux MLS range, i.e. `low[-high]`?
+fn is_selinux_range(s: &str) -> bool {
+    let mut levels = s.splitn(2, '-');
+    levels.all(is_selinux_level)
+}
+
+/// Is the given string a valid SELinux MLS level, i.e. `s0[:c0.c3,c5]`?
+fn is_selinux_level(s: &str) -> bool {
+    let mut parts = s.splitn(2, ':');
+
+    let sensitivity = parts.next().unwrap_or_default();
+    if !is_prefixed_number(sensitivity, 's') {
+        return false;
+    }
+
+    match parts.next() {
+        Some(categories) => categories.split(',').all(|category| {
+            let mut range = category.splitn(2, '.');
+            range.all(|c| is_prefixed_number(c, 'c'))
+        }),
+        None => true,
+    }
+}
+
+/// Is the given string a number with the given single-character prefix?
+fn is_prefixed_number(s: &str, prefix: char) -> bool {
+    match s.strip_prefix(prefix) {
+        Some(n) => !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
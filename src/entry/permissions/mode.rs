@@ -0,0 +1,551 @@
+//! Unix file modes
+
+use crate::error::{Error, ErrorKind};
+use anomaly::{ensure, fail, format_err};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// Unix file mode (i.e. file type and permissions)
+///
+/// See the `chmod(1)` and `inode(7)` manpages for more information.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Mode(u32);
+
+impl Mode {
+    /// Bitmask for the file type bits (`S_IFMT`)
+    pub const FILE_TYPE_MASK: u32 = 0o170_000;
+
+    /// Bitmask for all permission bits, including setuid, setgid, and sticky
+    pub const PERMISSIONS_MASK: u32 = 0o7777;
+
+    /// Set user ID on execution (`S_ISUID`)
+    pub const SETUID: u32 = 0o4000;
+
+    /// Set group ID on execution (`S_ISGID`)
+    pub const SETGID: u32 = 0o2000;
+
+    /// Sticky bit (`S_ISVTX`)
+    pub const STICKY: u32 = 0o1000;
+
+    /// Create a mode from the given file type and permission bits
+    pub fn new(file_type: Option<FileType>, permissions: u32) -> Result<Self, Error> {
+        ensure!(
+            permissions & !Self::PERMISSIONS_MASK == 0,
+            ErrorKind::Parse,
+            "invalid permission bits: {:o}",
+            permissions
+        );
+
+        Ok(Mode(file_type.map(u32::from).unwrap_or(0) | permissions))
+    }
+
+    /// Get the type of file this mode describes (if recorded)
+    pub fn file_type(self) -> Option<FileType> {
+        FileType::from_mode_bits(self.0 & Self::FILE_TYPE_MASK)
+    }
+
+    /// Get the permission bits, including setuid, setgid, and sticky
+    pub fn permissions(self) -> u32 {
+        self.0 & Self::PERMISSIONS_MASK
+    }
+
+    /// Replace the permission bits, retaining the file type
+    pub fn with_permissions(self, permissions: u32) -> Result<Self, Error> {
+        Self::new(self.file_type(), permissions)
+    }
+
+    /// Is the setuid bit set?
+    pub fn is_setuid(self) -> bool {
+        self.0 & Self::SETUID != 0
+    }
+
+    /// Is the setgid bit set?
+    pub fn is_setgid(self) -> bool {
+        self.0 & Self::SETGID != 0
+    }
+
+    /// Is the sticky bit set?
+    pub fn is_sticky(self) -> bool {
+        self.0 & Self::STICKY != 0
+    }
+
+    /// Access permitted to the owning user
+    pub fn user(self) -> Access {
+        Access::from_bits(self.0 >> 6)
+    }
+
+    /// Access permitted to members of the owning group
+    pub fn group(self) -> Access {
+        Access::from_bits(self.0 >> 3)
+    }
+
+    /// Access permitted to everyone else
+    pub fn other(self) -> Access {
+        Access::from_bits(self.0)
+    }
+
+    /// Format this mode as a string ala `ls -l`, e.g. `-rwxr-xr-x`
+    pub fn ls_formatted(self) -> String {
+        let mut s = String::with_capacity(10);
+        s.push(self.file_type().map(FileType::ls_char).unwrap_or('-'));
+
+        for (access, special, special_char) in &[
+            (self.user(), self.is_setuid(), 's'),
+            (self.group(), self.is_setgid(), 's'),
+            (self.other(), self.is_sticky(), 't'),
+        ] {
+            s.push(if access.read { 'r' } else { '-' });
+            s.push(if access.write { 'w' } else { '-' });
+            s.push(match (access.execute, special) {
+                (true, true) => *special_char,
+                (false, true) => special_char.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            });
+        }
+
+        s
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:o}", self)
+    }
+}
+
+impl fmt::Octal for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Octal::fmt(&self.0, f)
+    }
+}
+
+impl From<Mode> for u32 {
+    fn from(mode: Mode) -> u32 {
+        mode.0
+    }
+}
+
+impl TryFrom<u32> for Mode {
+    type Error = Error;
+
+    fn try_from(mode: u32) -> Result<Self, Error> {
+        let file_type_bits = mode & Self::FILE_TYPE_MASK;
+
+        ensure!(
+            file_type_bits == 0 || FileType::from_mode_bits(file_type_bits).is_some(),
+            ErrorKind::Parse,
+            "invalid file type in mode: {:o}",
+            mode
+        );
+
+        ensure!(
+            mode & !(Self::FILE_TYPE_MASK | Self::PERMISSIONS_MASK) == 0,
+            ErrorKind::Parse,
+            "undefined bits set in mode: {:o}",
+            mode
+        );
+
+        Ok(Mode(mode))
+    }
+}
+
+/// Parse a mode from either an octal string (e.g. `755`) or a symbolic
+/// `chmod`-style string applied to an empty mode (e.g. `u=rwx,go=rx`)
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        s.parse::<ModeChange>()?.apply(Mode(0), 0)
+    }
+}
+
+/// Types of files, as encoded in the upper bits of a mode
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileType {
+    /// Named pipe (`S_IFIFO`)
+    Fifo,
+
+    /// Character device (`S_IFCHR`)
+    CharDevice,
+
+    /// Directory (`S_IFDIR`)
+    Directory,
+
+    /// Block device (`S_IFBLK`)
+    BlockDevice,
+
+    /// Regular file (`S_IFREG`)
+    Regular,
+
+    /// Symbolic link (`S_IFLNK`)
+    Symlink,
+
+    /// Socket (`S_IFSOCK`)
+    Socket,
+}
+
+impl FileType {
+    /// Decode the file type from the `S_IFMT` bits of a mode
+    fn from_mode_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0o010_000 => Some(FileType::Fifo),
+            0o020_000 => Some(FileType::CharDevice),
+            0o040_000 => Some(FileType::Directory),
+            0o060_000 => Some(FileType::BlockDevice),
+            0o100_000 => Some(FileType::Regular),
+            0o120_000 => Some(FileType::Symlink),
+            0o140_000 => Some(FileType::Socket),
+            _ => None,
+        }
+    }
+
+    /// Character used to represent this file type by `ls -l`
+    pub fn ls_char(self) -> char {
+        match self {
+            FileType::Fifo => 'p',
+            FileType::CharDevice => 'c',
+            FileType::Directory => 'd',
+            FileType::BlockDevice => 'b',
+            FileType::Regular => '-',
+            FileType::Symlink => 'l',
+            FileType::Socket => 's',
+        }
+    }
+}
+
+impl From<FileType> for u32 {
+    fn from(file_type: FileType) -> u32 {
+        match file_type {
+            FileType::Fifo => 0o010_000,
+            FileType::CharDevice => 0o020_000,
+            FileType::Directory => 0o040_000,
+            FileType::BlockDevice => 0o060_000,
+            FileType::Regular => 0o100_000,
+            FileType::Symlink => 0o120_000,
+            FileType::Socket => 0o140_000,
+        }
+    }
+}
+
+/// Read/write/execute access for a class of users
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Access {
+    /// Read permission
+    pub read: bool,
+
+    /// Write permission
+    pub write: bool,
+
+    /// Execute (or for directories, search) permission
+    pub execute: bool,
+}
+
+impl Access {
+    /// Decode access from the lowest three bits of the given value
+    fn from_bits(bits: u32) -> Self {
+        Self {
+            read: bits & 0o4 != 0,
+            write: bits & 0o2 != 0,
+            execute: bits & 0o1 != 0,
+        }
+    }
+}
+
+/// Change to apply to a file's mode, ala `chmod(1)`.
+///
+/// These can either be octal (e.g. `644`), which replaces all permission
+/// bits, or symbolic (e.g. `u+x,go-w`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ModeChange {
+    /// Replace all permission bits with the given octal value
+    Octal(u32),
+
+    /// Apply symbolic clauses (in order)
+    Symbolic(Vec<Clause>),
+}
+
+impl ModeChange {
+    /// Apply this change to the given mode, retaining its file type.
+    ///
+    /// As with `chmod`, symbolic clauses which don't name any user classes
+    /// (e.g. `+r`) don't affect bits which are set in the given umask (see
+    /// [`umask`]).
+    pub fn apply(&self, mode: Mode, umask: u32) -> Result<Mode, Error> {
+        match self {
+            ModeChange::Octal(permissions) => mode.with_permissions(*permissions),
+            ModeChange::Symbolic(clauses) => {
+                let permissions = clauses.iter().fold(mode.permissions(), |bits, clause| {
+                    clause.apply(mode, bits, umask)
+                });
+
+                mode.with_permissions(permissions)
+            }
+        }
+    }
+}
+
+impl FromStr for ModeChange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        ensure!(!s.is_empty(), ErrorKind::Parse, "mode cannot be empty");
+
+        if s.chars().all(|c| c.is_digit(8)) {
+            let permissions = u32::from_str_radix(s, 8)
+                .map_err(|_| format_err!(ErrorKind::Parse, "invalid octal mode: `{}`", s))?;
+
+            ensure!(
+                permissions & !Mode::PERMISSIONS_MASK == 0,
+                ErrorKind::Parse,
+                "invalid octal mode: `{}`",
+                s
+            );
+
+            return Ok(ModeChange::Octal(permissions));
+        }
+
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(ModeChange::Symbolic)
+    }
+}
+
+/// Symbolic mode clause, e.g. `ug+rw`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Clause {
+    /// Bitmask of permission bits selected by the user classes in this
+    /// clause (i.e. `u`, `g`, `o`, `a`)
+    who: u32,
+
+    /// Were the user classes omitted (so the umask applies)?
+    umasked: bool,
+
+    /// Operations to perform
+    actions: Vec<(Operator, Perms)>,
+}
+
+impl Clause {
+    /// Apply this clause to the given permission bits of a file with the
+    /// given mode
+    fn apply(&self, mode: Mode, mut bits: u32, umask: u32) -> u32 {
+        let masked = if self.umasked { umask } else { 0 };
+
+        for (op, perms) in &self.actions {
+            let perms = perms.to_bits(mode, bits) & self.who & !masked;
+
+            bits = match op {
+                Operator::Add => bits | perms,
+                Operator::Remove => bits & !perms,
+                Operator::Set => {
+                    // `=` doesn't clear setuid/setgid on directories, ala GNU chmod
+                    let preserved = if mode.file_type() == Some(FileType::Directory) {
+                        Mode::SETUID | Mode::SETGID
+                    } else {
+                        0
+                    };
+
+                    (bits & !(self.who & !preserved)) | perms
+                }
+            };
+        }
+
+        bits
+    }
+}
+
+impl FromStr for Clause {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let op_start = s.find(['+', '-', '=']).ok_or_else(|| {
+            format_err!(ErrorKind::Parse, "missing operator in mode clause: `{}`", s)
+        })?;
+
+        let mut who = 0;
+
+        for c in s[..op_start].chars() {
+            who |= match c {
+                'u' => Mode::SETUID | 0o700,
+                'g' => Mode::SETGID | 0o070,
+                'o' => Mode::STICKY | 0o007,
+                'a' => Mode::PERMISSIONS_MASK,
+                _ => fail!(
+                    ErrorKind::Parse,
+                    "invalid user class `{}` in mode: `{}`",
+                    c,
+                    s
+                ),
+            };
+        }
+
+        // As with `chmod`, omitting the user class means "all", except for
+        // bits which are set in the umask
+        let umasked = who == 0;
+
+        if umasked {
+            who = Mode::PERMISSIONS_MASK;
+        }
+
+        let mut actions = vec![];
+        let mut rest = &s[op_start..];
+
+        while let Some(op_char) = rest.chars().next() {
+            let op = match op_char {
+                '+' => Operator::Add,
+                '-' => Operator::Remove,
+                '=' => Operator::Set,
+                _ => fail!(
+                    ErrorKind::Parse,
+                    "invalid operator `{}` in mode: `{}`",
+                    op_char,
+                    s
+                ),
+            };
+
+            rest = &rest[1..];
+            let perms_end = rest.find(['+', '-', '=']).unwrap_or(rest.len());
+
+            actions.push((op, rest[..perms_end].parse()?));
+            rest = &rest[perms_end..];
+        }
+
+        Ok(Self {
+            who,
+            umasked,
+            actions,
+        })
+    }
+}
+
+/// Symbolic mode operators
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Operator {
+    /// Add permissions (`+`)
+    Add,
+
+    /// Remove permissions (`-`)
+    Remove,
+
+    /// Set permissions exactly (`=`)
+    Set,
+}
+
+/// Permissions in a symbolic mode clause
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Perms {
+    /// Permission letters (`rwxXst`)
+    Letters {
+        /// `r`, `w`, `x`, `s`, and `t` as mode bits for all user classes
+        bits: u32,
+
+        /// `X`: execute only if a directory or already executable by someone
+        conditional_execute: bool,
+    },
+
+    /// Copy the permissions of a user class (`u`, `g`, or `o`)
+    Copy(u32),
+}
+
+impl Perms {
+    /// Compute the permission bits (for all user classes) these represent
+    fn to_bits(self, mode: Mode, current: u32) -> u32 {
+        match self {
+            Perms::Letters {
+                mut bits,
+                conditional_execute,
+            } => {
+                if conditional_execute
+                    && (mode.file_type() == Some(FileType::Directory) || current & 0o111 != 0)
+                {
+                    bits |= 0o111;
+                }
+
+                bits
+            }
+            Perms::Copy(shift) => {
+                let rwx = (current >> shift) & 0o7;
+                rwx << 6 | rwx << 3 | rwx
+            }
+        }
+    }
+}
+
+impl FromStr for Perms {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "u" => return Ok(Perms::Copy(6)),
+            "g" => return Ok(Perms::Copy(3)),
+            "o" => return Ok(Perms::Copy(0)),
+            _ => (),
+        }
+
+        let mut bits = 0;
+        let mut conditional_execute = false;
+
+        for c in s.chars() {
+            match c {
+                'r' => bits |= 0o444,
+                'w' => bits |= 0o222,
+                'x' => bits |= 0o111,
+                'X' => conditional_execute = true,
+                's' => bits |= Mode::SETUID | Mode::SETGID,
+                't' => bits |= Mode::STICKY,
+                _ => fail!(
+                    ErrorKind::Parse,
+                    "invalid permission `{}` in mode: `{}`",
+                    c,
+                    s
+                ),
+            }
+        }
+
+        Ok(Perms::Letters {
+            bits,
+            conditional_execute,
+        })
+    }
+}
+
+/// Get the file mode creation mask of the current process.
+///
+/// On Linux this is read from `/proc`. Elsewhere it has to be changed and then
+/// restored, so this shouldn't be called while other threads are creating
+/// files.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // `mode_t` is `u16` on some platforms
+pub fn umask() -> u32 {
+    use nix::sys::stat;
+
+    if let Some(mask) = read_proc_umask() {
+        return mask;
+    }
+
+    // Otherwise the only way to read the umask is to change it, so restore
+    // it afterward. It's briefly cleared for the whole process, so files
+    // created by other threads meanwhile could get broader permissions.
+    let mask = stat::umask(stat::Mode::empty());
+    stat::umask(mask);
+    mask.bits().into()
+}
+
+/// Get the file mode creation mask of the current process
+#[cfg(windows)]
+pub fn umask() -> u32 {
+    0
+}
+
+/// Read the file mode creation mask from `/proc/self/status` (on Linux 4.7
+/// and later), which unlike `umask(2)` doesn't require changing it
+#[cfg(unix)]
+fn read_proc_umask() -> Option<u32> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("Umask:"))?;
+    u32::from_str_radix(line["Umask:".len()..].trim(), 8).ok()
+}
@@ -42,6 +42,10 @@ pub enum ErrorKind {
     /// Invalid paths
     #[error("path error")]
     Path,
+
+    /// Attempt to access files outside of an archive's destination directory
+    #[error("path traversal")]
+    Traversal,
 }
 
 impl ErrorKind {
@@ -89,6 +93,12 @@ impl From<cryptouri::Error> for Error {
     }
 }
 
+impl From<ignore::Error> for Error {
+    fn from(err: ignore::Error) -> Self {
+        ErrorKind::Parse.context(err).into()
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
         ErrorKind::Io.context(err).into()
@@ -101,6 +111,19 @@ impl From<mime::FromStrError> for Error {
     }
 }
 
+#[cfg(unix)]
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Self {
+        ErrorKind::Io.context(err).into()
+    }
+}
+
+impl From<prost::DecodeError> for Error {
+    fn from(err: prost::DecodeError) -> Self {
+        ErrorKind::Parse.context(err).into()
+    }
+}
+
 impl From<prost::EncodeError> for Error {
     fn from(err: prost::EncodeError) -> Self {
         ErrorKind::Builder.context(err).into()
@@ -0,0 +1,117 @@
+//! Excluding files from archives with gitignore-style patterns
+
+use crate::error::Error;
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use std::path::Path;
+
+/// Name of the files containing per-directory exclusion patterns
+pub const IGNORE_FILE_NAME: &str = ".searignore";
+
+/// Set of gitignore-style patterns for excluding files from archives.
+///
+/// Patterns have the same semantics as in `.gitignore` files: a leading `!`
+/// negates a pattern (re-including files excluded by an earlier one), a
+/// leading `/` anchors it to the directory the patterns are relative to, and
+/// a trailing `/` only matches directories.
+///
+/// Patterns in the `.searignore` files of nested directories take precedence
+/// over those of the directories containing them, which in turn take
+/// precedence over global patterns.
+#[derive(Clone, Debug, Default)]
+pub struct Exclude {
+    /// Sets of patterns, ordered from the least to the most specific
+    matchers: Vec<Gitignore>,
+}
+
+impl Exclude {
+    /// Create a new exclusion set without any patterns
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add global patterns, which are relative to the current directory
+    pub fn add_patterns<S: AsRef<str>>(
+        &mut self,
+        patterns: impl IntoIterator<Item = S>,
+    ) -> Result<&mut Self, Error> {
+        let mut builder = GitignoreBuilder::new(".");
+
+        for pattern in patterns {
+            builder.add_line(None, pattern.as_ref())?;
+        }
+
+        self.push(builder)?;
+        Ok(self)
+    }
+
+    /// Add global patterns from a file containing one pattern per line. The
+    /// patterns are relative to the current directory.
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> Result<&mut Self, Error> {
+        let mut builder = GitignoreBuilder::new(".");
+
+        if let Some(err) = builder.add(path) {
+            return Err(err.into());
+        }
+
+        self.push(builder)?;
+        Ok(self)
+    }
+
+    /// Get the exclusion set for the contents of the given directory, which
+    /// adds the patterns in its `.searignore` file (if it has one)
+    pub fn enter_dir(&self, dir: impl AsRef<Path>) -> Result<Self, Error> {
+        self.enter_dir_in("", dir)
+    }
+
+    /// Get the exclusion set for the contents of a directory which is
+    /// relative to `base` on the local filesystem (ala `tar -C`). Patterns in
+    /// its `.searignore` file are matched against paths relative to `base`.
+    pub fn enter_dir_in(
+        &self,
+        base: impl AsRef<Path>,
+        dir: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        let ignore_file = base.as_ref().join(dir).join(IGNORE_FILE_NAME);
+        let mut exclude = self.clone();
+
+        if ignore_file.is_file() {
+            let mut builder = GitignoreBuilder::new(dir);
+
+            if let Some(err) = builder.add(ignore_file) {
+                return Err(err.into());
+            }
+
+            exclude.push(builder)?;
+        }
+
+        Ok(exclude)
+    }
+
+    /// Is the file at the given path excluded?
+    pub fn is_excluded(&self, path: impl AsRef<Path>, is_dir: bool) -> bool {
+        for matcher in self.matchers.iter().rev() {
+            match matcher.matched(path.as_ref(), is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => (),
+            }
+        }
+
+        false
+    }
+
+    /// Add the patterns from the given builder as the most specific set
+    fn push(&mut self, builder: GitignoreBuilder) -> Result<(), Error> {
+        let matcher = builder.build()?;
+
+        if !matcher.is_empty() {
+            self.matchers.push(matcher);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+//! Extract entries from `.sear` archives onto the local filesystem
+
+mod dir;
+
+use self::dir::{Dir, FileId, Stat};
+use crate::{
+    entry::{
+        digest::DigestReader,
+        permissions::{mode::umask, SELinuxLabel, SELinuxRestore},
+        Entry, Kind,
+    },
+    error::{Error, ErrorKind},
+    transform::Transform,
+};
+use anomaly::{ensure, fail, format_err};
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::{OsStr, OsString},
+    fs::File,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Archive extractor.
+///
+/// All files are created relative to a handle to the destination directory
+/// which is opened up front. Entries with absolute paths or `..` components
+/// are refused, as are entries which would be written through a symbolic
+/// link created earlier in the same extraction.
+#[derive(Debug)]
+pub struct Extractor {
+    /// Directory to extract entries into
+    destination: PathBuf,
+
+    /// Open handle to the destination directory
+    root: Dir,
+
+    /// Directories which have been extracted, along with the entries whose
+    /// permissions should be applied to them once extraction has finished
+    directories: Vec<(PathBuf, Entry)>,
+
+    /// Symbolic links which have been created by this extractor
+    links: HashSet<FileId>,
+
+    /// Top-level files and directories to relabel with the system's default
+    /// SELinux contexts once extraction has finished
+    relabel: BTreeSet<OsString>,
+
+    /// Use numerical UIDs/GIDs rather than names when restoring ownership
+    numeric_owner: bool,
+
+    /// Apply archived permissions exactly, including setuid/setgid/sticky
+    /// bits, rather than masking them with the umask
+    preserve_permissions: bool,
+
+    /// Restore the ownership of extracted files
+    same_owner: bool,
+
+    /// How to restore SELinux labels of extracted files
+    selinux: SELinuxRestore,
+
+    /// Rewrites the paths of entries before they're extracted
+    transform: Transform,
+
+    /// File mode creation mask of this process
+    umask: u32,
+}
+
+impl Extractor {
+    /// Create a new extractor which unpacks entries into the given directory.
+    ///
+    /// By default, ownership is restored only when running as the superuser.
+    ///
+    /// The umask is read here. Where it can't be read from `/proc` (i.e.
+    /// anywhere but Linux), it has to be changed and then restored, so this
+    /// shouldn't be called while other threads are creating files.
+    pub fn new(destination: impl Into<PathBuf>) -> Result<Self, Error> {
+        let destination = destination.into();
+        let root = Dir::open(&destination)?;
+
+        Ok(Self {
+            destination,
+            root,
+            directories: vec![],
+            links: HashSet::new(),
+            relabel: BTreeSet::new(),
+            numeric_owner: false,
+            preserve_permissions: false,
+            same_owner: is_superuser(),
+            selinux: SELinuxRestore::default(),
+            transform: Transform::default(),
+            umask: umask(),
+        })
+    }
+
+    /// Restore ownership using the numerical UID/GID stored in the archive,
+    /// ignoring user and group names
+    pub fn numeric_owner(&mut self, numeric_owner: bool) -> &mut Self {
+        self.numeric_owner = numeric_owner;
+        self
+    }
+
+    /// Apply archived permissions exactly (i.e. ignoring the umask), including
+    /// setuid, setgid, and sticky bits
+    pub fn preserve_permissions(&mut self, preserve_permissions: bool) -> &mut Self {
+        self.preserve_permissions = preserve_permissions;
+        self
+    }
+
+    /// Restore the ownership of extracted files (requires superuser)
+    pub fn same_owner(&mut self, same_owner: bool) -> &mut Self {
+        self.same_owner = same_owner;
+        self
+    }
+
+    /// Configure how SELinux labels of extracted files are restored
+    pub fn selinux(&mut self, selinux: SELinuxRestore) -> &mut Self {
+        self.selinux = selinux;
+        self
+    }
+
+    /// Rewrite the paths of entries (and the targets of hard links) before
+    /// extracting them. Entries with nothing left of their paths are skipped.
+    pub fn transform(&mut self, transform: Transform) -> &mut Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Extract the given entry, reading its contents from the provided reader
+    /// and checking them against the entry's digest (if recorded)
+    pub fn extract(&mut self, entry: &Entry, reader: impl io::Read) -> Result<(), Error> {
+        let transformed;
+        let entry = if self.transform.is_identity() {
+            entry
+        } else {
+            match self.transform.apply_to_entry(entry)? {
+                Some(entry) => {
+                    transformed = entry;
+                    &transformed
+                }
+                None => return Ok(()),
+            }
+        };
+
+        let components = safe_components(&entry.path)?;
+
+        let (name, parents) = match components.split_last() {
+            Some(split) => split,
+            // The destination directory itself (i.e. `.`) already exists
+            None if entry.kind == Kind::Directory => return Ok(()),
+            None => fail!(ErrorKind::Path, "empty path in archive"),
+        };
+
+        // Tombstones remove files extracted from an earlier archive, without
+        // creating any missing parent directories
+        if entry.kind == Kind::Deleted {
+            return self.remove(parents, name, &entry.path);
+        }
+
+        let dir = self.open_parents(parents, &entry.path)?;
+        let mut file = None;
+
+        match &entry.kind {
+            Kind::File => {
+                // Write the contents to a temporary file, which only replaces
+                // whatever already exists once they've been verified. Renaming
+                // replaces an existing symbolic link rather than writing
+                // through it.
+                let (temp_name, mut f) = dir.create_temp_file()?;
+
+                let result = write_contents(entry, reader, &mut f)
+                    .and_then(|()| dir.rename(&temp_name, name));
+
+                if let Err(e) = result {
+                    dir.remove_file(&temp_name)?;
+                    return Err(e);
+                }
+
+                file = Some(f);
+            }
+            Kind::Directory => {
+                self.open_subdir(&dir, name, &entry.path)?;
+            }
+            Kind::Link { symbolic, target } => {
+                if !*symbolic {
+                    fail!(ErrorKind::Path, "hard links unsupported");
+                }
+
+                dir.remove_file(name)?;
+                dir.symlink(target, name)?;
+
+                // Remember this link so later entries can't be written through it
+                if let Some(stat) = dir.stat(name)? {
+                    self.links.insert(stat.id);
+                }
+            }
+            Kind::Deleted => unreachable!("tombstones are handled above"),
+        }
+
+        // Ownership must be changed before the mode, since `chown` clears
+        // the setuid and setgid bits
+        if self.same_owner {
+            let (uid, gid) = entry.owner.resolve(self.numeric_owner);
+            dir.chown(name, uid, gid)?;
+        }
+
+        match entry.kind {
+            // Directory permissions are applied after their contents have been
+            // extracted so read-only directories don't prevent extraction
+            Kind::Directory => self.directories.push((entry.path.clone(), entry.clone())),
+            Kind::File => {
+                if let Some(file) = &file {
+                    dir::set_permissions(file, self.permissions_for(entry))?;
+                }
+            }
+            // Symbolic links don't have permissions of their own
+            Kind::Link { .. } | Kind::Deleted => (),
+        }
+
+        // Directory timestamps are also applied once extraction has finished,
+        // since extracting their contents updates them
+        if entry.kind != Kind::Directory {
+            set_times(&dir, name, entry)?;
+        }
+
+        match self.selinux {
+            SELinuxRestore::Skip => (),
+            SELinuxRestore::Archived => {
+                for label in &entry.permissions.selinux_labels {
+                    dir.set_selinux_label(name, label).map_err(|e| {
+                        format_err!(
+                            ErrorKind::Io,
+                            "couldn't set SELinux label on `{}`: {}",
+                            entry.path.display(),
+                            e
+                        )
+                    })?;
+                }
+            }
+            // Default labels are looked up by path, so rather than running
+            // `restorecon` on each file, the trees they're in are relabeled
+            // all at once when extraction finishes
+            SELinuxRestore::Default => {
+                self.relabel.insert(components[0].to_os_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finish extracting, applying the permissions of extracted directories
+    /// (and default SELinux labels, if requested)
+    pub fn finish(mut self) -> Result<(), Error> {
+        // Apply permissions to the most deeply nested directories first
+        while let Some((path, entry)) = self.directories.pop() {
+            let components = safe_components(&path)?;
+
+            if let Some((name, parents)) = components.split_last() {
+                let dir = self.open_parents(parents, &path)?;
+                let subdir = self.open_subdir(&dir, name, &path)?;
+                subdir.set_permissions(self.permissions_for(&entry))?;
+                set_times(&dir, name, &entry)?;
+            }
+        }
+
+        let relabel = self
+            .relabel
+            .iter()
+            .map(|name| self.destination.join(name))
+            .collect::<Vec<_>>();
+
+        SELinuxLabel::restore_default(&relabel)
+    }
+
+    /// Remove the file at the given path if it exists. Directories are only
+    /// removed if they're empty.
+    fn remove(&mut self, parents: &[&OsStr], name: &OsStr, path: &Path) -> Result<(), Error> {
+        let mut dir = self.root.try_clone()?;
+
+        for parent in parents {
+            match dir.stat(parent)? {
+                Some(stat) => self.check_traversal(&stat, path)?,
+                None => return Ok(()),
+            }
+
+            dir = dir.open_dir(parent)?;
+        }
+
+        match dir.stat(name)? {
+            Some(stat) if stat.is_dir => {
+                // Don't recreate the directory when applying its permissions
+                self.directories.retain(|(dir_path, _)| dir_path != path);
+                dir.remove_dir(name)
+            }
+            Some(_) => dir.remove_file(name),
+            None => Ok(()),
+        }
+    }
+
+    /// Compute the permissions to apply to an extracted entry
+    fn permissions_for(&self, entry: &Entry) -> u32 {
+        let permissions = entry.permissions.mode.permissions();
+
+        if self.preserve_permissions {
+            permissions
+        } else {
+            permissions & 0o777 & !self.umask
+        }
+    }
+
+    /// Open the directory containing an entry by walking down from the
+    /// destination directory one component at a time, creating any
+    /// directories which are missing
+    fn open_parents(&self, parents: &[&OsStr], path: &Path) -> Result<Dir, Error> {
+        let mut dir = self.root.try_clone()?;
+
+        for name in parents {
+            dir = self.open_subdir(&dir, name, path)?;
+        }
+
+        Ok(dir)
+    }
+
+    /// Open (creating if necessary) a subdirectory, refusing to traverse
+    /// symbolic links created by this extractor
+    fn open_subdir(&self, dir: &Dir, name: &OsStr, path: &Path) -> Result<Dir, Error> {
+        match dir.stat(name)? {
+            Some(stat) => self.check_traversal(&stat, path)?,
+            None => dir.create_dir(name)?,
+        }
+
+        dir.open_dir(name)
+    }
+
+    /// Refuse to traverse symbolic links created by this extractor
+    fn check_traversal(&self, stat: &Stat, path: &Path) -> Result<(), Error> {
+        ensure!(
+            !(stat.is_symlink && self.links.contains(&stat.id)),
+            ErrorKind::Traversal,
+            "refusing to extract through symbolic link: `{}`",
+            path.display()
+        );
+
+        Ok(())
+    }
+}
+
+/// Restore the archived access and modification times of an entry
+fn set_times(dir: &Dir, name: &OsStr, entry: &Entry) -> Result<(), Error> {
+    let accessed = entry.attributes.accessed_at.map(|t| t.to_system_time());
+    let modified = entry.attributes.modified_at.map(|t| t.to_system_time());
+
+    if accessed.is_some() || modified.is_some() {
+        dir.set_times(name, accessed, modified)?;
+    }
+
+    Ok(())
+}
+
+/// Split a path from an archive into its components, refusing absolute paths
+/// and paths containing `..`
+fn safe_components(path: &Path) -> Result<Vec<&OsStr>, Error> {
+    let mut components = vec![];
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name),
+            Component::CurDir => (),
+            Component::Prefix(_) | Component::RootDir | Component::ParentDir => fail!(
+                ErrorKind::Traversal,
+                "refusing to extract unsafe path: `{}`",
+                path.display()
+            ),
+        }
+    }
+
+    Ok(components)
+}
+
+/// Write the contents of a regular file, checking them against the entry's
+/// length and digest (if recorded)
+fn write_contents(entry: &Entry, reader: impl io::Read, file: &mut File) -> Result<(), Error> {
+    let mut reader = DigestReader::new(reader);
+    let length = io::copy(&mut reader, file)?;
+
+    ensure!(
+        length == entry.length,
+        ErrorKind::Parse,
+        "truncated entry: `{}`",
+        entry.path.display()
+    );
+
+    if let Some(digest) = entry.digest {
+        ensure!(
+            reader.digest() == digest,
+            ErrorKind::Crypto,
+            "digest mismatch: `{}`",
+            entry.path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Are we running as the superuser (i.e. root)?
+#[cfg(unix)]
+fn is_superuser() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+/// Are we running as the superuser (i.e. root)?
+#[cfg(windows)]
+fn is_superuser() -> bool {
+    false
+}
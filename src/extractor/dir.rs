@@ -0,0 +1,416 @@
+//! Directory handles which extraction is performed relative to.
+//!
+//! All operations take a single path component (`name`) which is resolved
+//! relative to an already-opened directory, rather than a full path which
+//! is (re-)resolved from the current directory each time.
+
+use crate::{entry::permissions::SELinuxLabel, error::Error};
+use getrandom::getrandom;
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    path::Path,
+    time::SystemTime,
+};
+
+#[cfg(unix)]
+use nix::{
+    errno::Errno,
+    fcntl::{openat, renameat, AtFlags, OFlag, AT_FDCWD},
+    sys::{
+        stat::{fstatat, mkdirat, utimensat, Mode, SFlag, UtimensatFlags},
+        time::TimeSpec,
+    },
+    unistd::{fchownat, symlinkat, unlinkat, Gid, Uid, UnlinkatFlags},
+};
+
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+
+#[cfg(windows)]
+use std::{io, path::PathBuf};
+
+/// Unique identifier of a file on the local filesystem (device and inode)
+#[cfg(unix)]
+pub type FileId = (nix::libc::dev_t, nix::libc::ino_t);
+
+/// Unique identifier of a file on the local filesystem (its path)
+#[cfg(windows)]
+pub type FileId = PathBuf;
+
+/// Identity and type of a directory entry (without following symlinks)
+#[derive(Clone, Debug)]
+pub struct Stat {
+    /// Is this entry a directory?
+    pub is_dir: bool,
+
+    /// Is this entry a symbolic link?
+    pub is_symlink: bool,
+
+    /// Unique identifier of this entry
+    pub id: FileId,
+}
+
+/// Handle to an open directory
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Dir {
+    /// Open file descriptor for the directory
+    fd: OwnedFd,
+}
+
+#[cfg(unix)]
+impl Dir {
+    /// Open the directory at the given path
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC;
+        let fd = openat(AT_FDCWD, path, flags, Mode::empty())?;
+        Ok(Self { fd })
+    }
+
+    /// Duplicate this directory handle
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Ok(Self {
+            fd: self.fd.try_clone()?,
+        })
+    }
+
+    /// Open a subdirectory of this directory
+    pub fn open_dir(&self, name: &OsStr) -> Result<Self, Error> {
+        let flags = OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC;
+        let fd = openat(&self.fd, name, flags, Mode::empty())?;
+        Ok(Self { fd })
+    }
+
+    /// Get the type and identity of an entry in this directory, or `None`
+    /// if it doesn't exist
+    pub fn stat(&self, name: &OsStr) -> Result<Option<Stat>, Error> {
+        let stat = match fstatat(&self.fd, name, AtFlags::AT_SYMLINK_NOFOLLOW) {
+            Ok(stat) => stat,
+            Err(Errno::ENOENT) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let file_type = SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT;
+
+        Ok(Some(Stat {
+            is_dir: file_type == SFlag::S_IFDIR,
+            is_symlink: file_type == SFlag::S_IFLNK,
+            id: (stat.st_dev, stat.st_ino),
+        }))
+    }
+
+    /// Create a subdirectory (with permissions subject to the umask)
+    pub fn create_dir(&self, name: &OsStr) -> Result<(), Error> {
+        mkdirat(
+            &self.fd,
+            name,
+            Mode::S_IRWXU | Mode::S_IRWXG | Mode::S_IRWXO,
+        )?;
+        Ok(())
+    }
+
+    /// Create a new file with a random name which is only accessible by its
+    /// owner, returning its name along with the file
+    pub fn create_temp_file(&self) -> Result<(OsString, File), Error> {
+        loop {
+            let name = temp_name();
+
+            match self.create_fd(&name) {
+                Ok(fd) => return Ok((name, File::from(fd))),
+                Err(Errno::EEXIST) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Rename an entry of this directory, replacing any non-directory entry
+    /// which already exists with the new name (rather than following it if
+    /// it's a symbolic link)
+    pub fn rename(&self, from: &OsStr, to: &OsStr) -> Result<(), Error> {
+        renameat(&self.fd, from, &self.fd, to)?;
+        Ok(())
+    }
+
+    /// Remove a non-directory entry from this directory if it exists
+    pub fn remove_file(&self, name: &OsStr) -> Result<(), Error> {
+        match unlinkat(&self.fd, name, UnlinkatFlags::NoRemoveDir) {
+            Ok(()) | Err(Errno::ENOENT) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove a subdirectory of this directory if it exists and is empty
+    pub fn remove_dir(&self, name: &OsStr) -> Result<(), Error> {
+        match unlinkat(&self.fd, name, UnlinkatFlags::RemoveDir) {
+            Ok(()) | Err(Errno::ENOENT) | Err(Errno::ENOTEMPTY) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Create a symbolic link to the given target
+    pub fn symlink(&self, target: &Path, name: &OsStr) -> Result<(), Error> {
+        symlinkat(target, &self.fd, name)?;
+        Ok(())
+    }
+
+    /// Change the owner of an entry without following symbolic links
+    pub fn chown(&self, name: &OsStr, uid: Option<u32>, gid: Option<u32>) -> Result<(), Error> {
+        fchownat(
+            &self.fd,
+            name,
+            uid.map(Uid::from_raw),
+            gid.map(Gid::from_raw),
+            AtFlags::AT_SYMLINK_NOFOLLOW,
+        )?;
+
+        Ok(())
+    }
+
+    /// Change the permission bits of this directory
+    pub fn set_permissions(&self, permissions: u32) -> Result<(), Error> {
+        set_permissions(&File::from(self.fd.try_clone()?), permissions)
+    }
+
+    /// Set the access and modification times of an entry (with nanosecond
+    /// precision) without following symbolic links. Times which are `None`
+    /// are left unchanged.
+    pub fn set_times(
+        &self,
+        name: &OsStr,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let to_timespec = |time: Option<SystemTime>| match time {
+            Some(time) => match time.duration_since(SystemTime::UNIX_EPOCH) {
+                Ok(duration) => TimeSpec::from(duration),
+                Err(e) => -TimeSpec::from(e.duration()),
+            },
+            None => TimeSpec::UTIME_OMIT,
+        };
+
+        utimensat(
+            &self.fd,
+            name,
+            &to_timespec(accessed),
+            &to_timespec(modified),
+            UtimensatFlags::NoFollowSymlink,
+        )?;
+
+        Ok(())
+    }
+
+    /// Set the SELinux label of an entry without following symbolic links.
+    ///
+    /// The entry is opened first, and labeled through that handle rather than
+    /// by its path, so it can't be swapped for something else meanwhile.
+    #[cfg(target_os = "linux")]
+    pub fn set_selinux_label(&self, name: &OsStr, label: &SELinuxLabel) -> Result<(), Error> {
+        use std::os::fd::AsRawFd;
+
+        let flags = OFlag::O_PATH | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+        let fd = openat(&self.fd, name, flags, Mode::empty())?;
+
+        // Extended attributes can't be set through `O_PATH` handles directly,
+        // only through their magic links in `/proc`, which resolve to the
+        // opened entry itself (even if it's a symbolic link)
+        xattr::set_deref(
+            format!("/proc/self/fd/{}", fd.as_raw_fd()),
+            SELinuxLabel::XATTR_NAME,
+            label.as_ref().as_bytes(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Set the SELinux label of an entry without following symbolic links
+    #[cfg(not(target_os = "linux"))]
+    pub fn set_selinux_label(&self, _name: &OsStr, _label: &SELinuxLabel) -> Result<(), Error> {
+        use crate::error::ErrorKind;
+        anomaly::fail!(
+            ErrorKind::Argument,
+            "SELinux labels are only supported on Linux"
+        )
+    }
+
+    /// Create a new file which is only accessible by its owner, failing if
+    /// anything (including a symbolic link) already exists with this name
+    fn create_fd(&self, name: &OsStr) -> nix::Result<OwnedFd> {
+        let flags =
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+
+        openat(&self.fd, name, flags, Mode::S_IRUSR | Mode::S_IWUSR)
+    }
+}
+
+/// Handle to an open directory
+#[cfg(windows)]
+#[derive(Debug)]
+pub struct Dir {
+    /// Path to the directory
+    path: PathBuf,
+}
+
+#[cfg(windows)]
+impl Dir {
+    /// Open the directory at the given path
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        if !fs::metadata(path)?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::Other, "not a directory").into());
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Duplicate this directory handle
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Ok(Self {
+            path: self.path.clone(),
+        })
+    }
+
+    /// Open a subdirectory of this directory
+    pub fn open_dir(&self, name: &OsStr) -> Result<Self, Error> {
+        Self::open(&self.path.join(name))
+    }
+
+    /// Get the type and identity of an entry in this directory, or `None`
+    /// if it doesn't exist
+    pub fn stat(&self, name: &OsStr) -> Result<Option<Stat>, Error> {
+        let path = self.path.join(name);
+
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(Stat {
+            is_dir: metadata.is_dir(),
+            is_symlink: metadata.file_type().is_symlink(),
+            id: path,
+        }))
+    }
+
+    /// Create a subdirectory
+    pub fn create_dir(&self, name: &OsStr) -> Result<(), Error> {
+        fs::create_dir(self.path.join(name))?;
+        Ok(())
+    }
+
+    /// Create a new file, failing if anything already exists with this name
+    fn create_file(&self, name: &OsStr) -> Result<File, Error> {
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.path.join(name))?;
+
+        Ok(file)
+    }
+
+    /// Create a new file with a random name, returning its name along with
+    /// the file
+    pub fn create_temp_file(&self) -> Result<(OsString, File), Error> {
+        loop {
+            let name = temp_name();
+
+            match self.create_file(&name) {
+                Ok(file) => return Ok((name, file)),
+                Err(_) if self.stat(&name)?.is_some() => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Rename an entry of this directory, replacing any file which already
+    /// exists with the new name
+    pub fn rename(&self, from: &OsStr, to: &OsStr) -> Result<(), Error> {
+        fs::rename(self.path.join(from), self.path.join(to))?;
+        Ok(())
+    }
+
+    /// Remove a non-directory entry from this directory if it exists
+    pub fn remove_file(&self, name: &OsStr) -> Result<(), Error> {
+        match fs::remove_file(self.path.join(name)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove a subdirectory of this directory if it exists and is empty
+    pub fn remove_dir(&self, name: &OsStr) -> Result<(), Error> {
+        match fs::remove_dir(self.path.join(name)) {
+            Ok(()) => Ok(()),
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    || e.kind() == io::ErrorKind::DirectoryNotEmpty =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Create a symbolic link to the given target
+    pub fn symlink(&self, target: &Path, name: &OsStr) -> Result<(), Error> {
+        std::os::windows::fs::symlink_file(target, self.path.join(name))?;
+        Ok(())
+    }
+
+    /// Change the owner of an entry without following symbolic links
+    pub fn chown(&self, _name: &OsStr, _uid: Option<u32>, _gid: Option<u32>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Change the permission bits of this directory
+    pub fn set_permissions(&self, _permissions: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Set the access and modification times of an entry
+    pub fn set_times(
+        &self,
+        _name: &OsStr,
+        _accessed: Option<SystemTime>,
+        _modified: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Set the SELinux label of an entry
+    pub fn set_selinux_label(&self, _name: &OsStr, _label: &SELinuxLabel) -> Result<(), Error> {
+        use crate::error::ErrorKind;
+        anomaly::fail!(ErrorKind::Argument, "SELinux labels unsupported on Windows")
+    }
+}
+
+/// Change the permission bits of an open file
+#[cfg(unix)]
+pub fn set_permissions(file: &File, permissions: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(permissions))?;
+    Ok(())
+}
+
+/// Change the permission bits of an open file
+#[cfg(windows)]
+pub fn set_permissions(_file: &File, _permissions: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Generate a random name for a temporary file, which is hidden and unlikely
+/// to collide with anything else
+fn temp_name() -> OsString {
+    let mut bytes = [0u8; 8];
+    getrandom(&mut bytes).expect("RNG failure!");
+
+    let suffix = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!(".sear-{}.tmp", suffix).into()
+}
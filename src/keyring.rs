@@ -55,7 +55,7 @@ impl KeyRing {
         // TODO(tarcieri): support for more than one key in the keyring
         match self.symmetric_keys.len() {
             0 => None,
-            1 => self.symmetric_keys.get(0),
+            1 => self.symmetric_keys.first(),
             _ => panic!("only one symmetric key per keyring presently supported"),
         }
     }
@@ -12,11 +12,24 @@
 #[macro_use]
 pub mod error;
 
+pub mod archive;
 pub mod builder;
 pub mod crypto;
+pub mod diff;
 pub mod entry;
+pub mod exclude;
+pub mod extractor;
 pub mod keyring;
 pub mod protos;
+pub mod snapshot;
+pub mod transform;
 pub mod uuid;
 
-pub use self::{builder::Builder, entry::Entry, error::Error, keyring::KeyRing};
+pub use self::{
+    archive::{Archive, StreamingArchive},
+    builder::Builder,
+    entry::Entry,
+    error::Error,
+    extractor::Extractor,
+    keyring::KeyRing,
+};
@@ -6,6 +6,7 @@ pub mod entry;
 pub mod footer;
 pub mod header;
 pub mod metadata;
+pub mod snapshot;
 pub mod timestamp;
 
 pub use self::{
@@ -13,6 +14,7 @@ pub use self::{
     footer::Footer,
     header::Header,
     metadata::{Index, Metadata},
+    snapshot::Snapshot,
     timestamp::Tai64n,
 };
 
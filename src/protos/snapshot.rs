@@ -0,0 +1,3 @@
+//! Snapshots of the local filesystem used to create incremental archives
+
+include!(concat!(env!("OUT_DIR"), "/sear.snapshot.rs"));
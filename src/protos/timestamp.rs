@@ -1,7 +1,9 @@
 //! TAI64N timestamps
 
+use crate::error::{Error, ErrorKind};
+use anomaly::format_err;
 use chrono::{DateTime, Utc};
-use std::time::SystemTime;
+use std::{convert::TryFrom, time::SystemTime};
 
 include!(concat!(env!("OUT_DIR"), "/sear.timestamp.rs"));
 
@@ -31,3 +33,12 @@ impl From<tai64::TAI64N> for Tai64n {
         }
     }
 }
+
+impl TryFrom<&Tai64n> for tai64::TAI64N {
+    type Error = Error;
+
+    fn try_from(t: &Tai64n) -> Result<Self, Error> {
+        tai64::TAI64N::from_slice(&t.value)
+            .map_err(|e| format_err!(ErrorKind::Parse, "invalid TAI64N timestamp: {}", e).into())
+    }
+}
@@ -0,0 +1,214 @@
+//! Snapshots of the local filesystem, used to create incremental archives
+
+use crate::{
+    entry::{decode_path, encode_path},
+    error::{Error, ErrorKind},
+    protos::{self, MessageExt},
+};
+use anomaly::ensure;
+use prost::Message;
+use std::{
+    collections::BTreeMap,
+    convert::{TryFrom, TryInto},
+    ffi::OsString,
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+use tai64::TAI64N;
+
+/// Magic bytes at the start of every snapshot file
+pub const MAGIC_BYTES: &[u8; 15] = b"sear-snapshot:0";
+
+/// Snapshot of the state of the files on the local filesystem when an
+/// archive was created.
+///
+/// Comparing a snapshot against the current state of the filesystem finds
+/// the files which are new, changed, or deleted, which is all an incremental
+/// archive needs to contain.
+///
+/// Snapshots are stored unencrypted, and therefore disclose the names of the
+/// files in the archives they describe.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Snapshot {
+    /// Records of each file, keyed by their paths within the archive
+    records: BTreeMap<PathBuf, Record>,
+}
+
+impl Snapshot {
+    /// Create a new, empty snapshot
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a snapshot from the given file.
+    ///
+    /// If the file doesn't exist, an empty snapshot is returned, so every file
+    /// is considered new (i.e. a level 0 backup).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let bytes = match fs::read(path.as_ref()) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        ensure!(
+            bytes.starts_with(MAGIC_BYTES),
+            ErrorKind::Parse,
+            "not a snapshot file: `{}`",
+            path.as_ref().display()
+        );
+
+        protos::Snapshot::decode(&bytes[MAGIC_BYTES.len()..])?.try_into()
+    }
+
+    /// Save this snapshot to the given file.
+    ///
+    /// The snapshot is written to a temporary file which then replaces the
+    /// given one, so an interrupted save won't clobber the previous snapshot.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut bytes = MAGIC_BYTES.to_vec();
+        bytes.extend_from_slice(&protos::Snapshot::try_from(self.clone())?.to_vec()?);
+
+        let mut temp_name = OsString::from(".");
+        temp_name.push(path.file_name().unwrap_or_default());
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let result = fs::write(&temp_path, &bytes).and_then(|()| fs::rename(&temp_path, path));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        Ok(result?)
+    }
+
+    /// Get the record for the file at the given path (within the archive)
+    pub fn get(&self, path: &Path) -> Option<&Record> {
+        self.records.get(path)
+    }
+
+    /// Record the state of the file at the given path (within the archive)
+    pub fn insert(&mut self, path: impl Into<PathBuf>, record: Record) {
+        self.records.insert(path.into(), record);
+    }
+
+    /// Is the given record for a new file, or one which has changed since
+    /// this snapshot was taken?
+    pub fn is_changed(&self, path: &Path, record: &Record) -> bool {
+        self.get(path) != Some(record)
+    }
+
+    /// Get the paths of files in this snapshot which are absent from a newer
+    /// one, i.e. files which have since been deleted.
+    ///
+    /// Paths are ordered with the contents of directories ahead of the
+    /// directories themselves, so they can be removed in order.
+    pub fn deleted(&self, newer: &Snapshot) -> Vec<PathBuf> {
+        self.records
+            .keys()
+            .rev()
+            .filter(|path| newer.get(path).is_none())
+            .cloned()
+            .collect()
+    }
+}
+
+impl TryFrom<Snapshot> for protos::Snapshot {
+    type Error = Error;
+
+    fn try_from(snapshot: Snapshot) -> Result<Self, Error> {
+        let records = snapshot
+            .records
+            .into_iter()
+            .map(|(path, record)| {
+                let (path, path_bytes) = encode_path(&path)?;
+
+                Ok(protos::snapshot::Record {
+                    path,
+                    path_bytes,
+                    device: record.device,
+                    inode: record.inode,
+                    modified_at: record.modified_at.map(Into::into),
+                    length: record.length,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self { records })
+    }
+}
+
+impl TryFrom<protos::Snapshot> for Snapshot {
+    type Error = Error;
+
+    fn try_from(snapshot: protos::Snapshot) -> Result<Self, Error> {
+        let mut records = BTreeMap::new();
+
+        for record in snapshot.records {
+            let path = decode_path(record.path, record.path_bytes)?;
+
+            records.insert(
+                path,
+                Record {
+                    device: record.device,
+                    inode: record.inode,
+                    modified_at: record
+                        .modified_at
+                        .as_ref()
+                        .map(TryInto::try_into)
+                        .transpose()?,
+                    length: record.length,
+                },
+            );
+        }
+
+        Ok(Self { records })
+    }
+}
+
+/// State of an individual file on the local filesystem
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    /// ID of the device containing the file
+    pub device: u64,
+
+    /// Inode number of the file
+    pub inode: u64,
+
+    /// Date when the file was last modified (if available)
+    pub modified_at: Option<TAI64N>,
+
+    /// Length of the file in bytes
+    pub length: u64,
+}
+
+impl Record {
+    /// Get the record for a file on the local filesystem from its
+    /// (`lstat`-style) metadata
+    pub fn for_metadata(metadata: &Metadata) -> Self {
+        let (device, inode) = file_id(metadata);
+
+        Self {
+            device,
+            inode,
+            modified_at: metadata.modified().ok().map(Into::into),
+            length: metadata.len(),
+        }
+    }
+}
+
+/// Get the device and inode numbers of a file
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+/// Get the device and inode numbers of a file (unavailable on Windows)
+#[cfg(windows)]
+fn file_id(_metadata: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
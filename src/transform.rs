@@ -0,0 +1,290 @@
+//! Rewriting the paths of entries when archiving or extracting them
+
+use crate::{
+    entry::{self, Entry, Kind},
+    error::{Error, ErrorKind},
+};
+use anomaly::{ensure, fail, format_err};
+use regex::{Regex, RegexBuilder};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Rewrites the paths of entries, e.g. to add or remove a top-level
+/// directory.
+///
+/// Leading components are stripped first, then each sed-style rule is
+/// applied in turn. The targets of hard links are rewritten the same way as
+/// paths, but the targets of symbolic links are left as-is, since they're
+/// relative to the link rather than the root of the archive.
+///
+/// Rewritten paths are normalized again, so a rule can't introduce `..`
+/// components, nor make a path absolute unless absolute paths are preserved.
+#[derive(Clone, Debug, Default)]
+pub struct Transform {
+    /// Number of leading path components to remove
+    strip_components: usize,
+
+    /// Allow rules to produce absolute paths?
+    preserve_absolute: bool,
+
+    /// Substitutions to apply to paths, in order
+    rules: Vec<Rule>,
+}
+
+impl Transform {
+    /// Create a new transform which leaves paths unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove the given number of leading components from paths. Paths with
+    /// no more components than that are removed entirely.
+    pub fn strip_components(&mut self, strip_components: usize) -> &mut Self {
+        self.strip_components = strip_components;
+        self
+    }
+
+    /// Allow rules to produce absolute paths (ala `-P`), rather than
+    /// rejecting them
+    pub fn preserve_absolute(&mut self, preserve_absolute: bool) -> &mut Self {
+        self.preserve_absolute = preserve_absolute;
+        self
+    }
+
+    /// Add a sed-style substitution, e.g. `s,^old/,new/,`.
+    ///
+    /// Any character may be used as the delimiter. Replacements may refer to
+    /// the whole match with `&` and to capture groups with `\1` to `\9`. The
+    /// `g` flag replaces every match rather than just the first, and the `i`
+    /// flag makes matching case-insensitive.
+    pub fn add_rule(&mut self, expression: &str) -> Result<&mut Self, Error> {
+        self.rules.push(expression.parse()?);
+        Ok(self)
+    }
+
+    /// Does this transform leave every path unchanged?
+    pub fn is_identity(&self) -> bool {
+        self.strip_components == 0 && self.rules.is_empty()
+    }
+
+    /// Rewrite the given path, returning `None` if nothing is left of it.
+    ///
+    /// Substitutions are only applied to paths which are valid UTF-8.
+    /// Rewritten paths containing `..` are rejected, as are absolute ones
+    /// unless [`Transform::preserve_absolute`] is set.
+    pub fn apply(&self, path: &Path) -> Result<Option<PathBuf>, Error> {
+        let mut path = path
+            .components()
+            .skip(self.strip_components)
+            .collect::<PathBuf>();
+
+        if let Some(s) = path.to_str() {
+            let mut s = s.to_owned();
+
+            for rule in &self.rules {
+                s = rule.apply(&s);
+            }
+
+            path = PathBuf::from(s);
+        }
+
+        ensure!(
+            self.preserve_absolute || !path.has_root(),
+            ErrorKind::Path,
+            "transform produced absolute path: `{}`",
+            path.display()
+        );
+
+        let path = entry::normalize_path(&path, self.preserve_absolute)?;
+
+        if path.as_os_str().is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(path))
+        }
+    }
+
+    /// Rewrite the path of the given entry (and its target if it's a hard
+    /// link), returning `None` if the entry should be skipped because
+    /// nothing is left of either.
+    pub fn apply_to_entry(&self, entry: &Entry) -> Result<Option<Entry>, Error> {
+        let mut entry = entry.clone();
+
+        entry.path = match self.apply(&entry.path)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        if let Kind::Link {
+            symbolic: false,
+            target,
+        } = &mut entry.kind
+        {
+            *target = match self.apply(target)? {
+                Some(target) => target,
+                None => return Ok(None),
+            };
+        }
+
+        Ok(Some(entry))
+    }
+}
+
+/// Sed-style substitution rule
+#[derive(Clone, Debug)]
+struct Rule {
+    /// Pattern to search for
+    regex: Regex,
+
+    /// Replacement, in the syntax of [`Regex::replace`]
+    replacement: String,
+
+    /// Replace every match rather than just the first?
+    global: bool,
+}
+
+impl Rule {
+    /// Apply this rule to the given string
+    fn apply(&self, s: &str) -> String {
+        if self.global {
+            self.regex.replace_all(s, self.replacement.as_str())
+        } else {
+            self.regex.replace(s, self.replacement.as_str())
+        }
+        .into_owned()
+    }
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(expression: &str) -> Result<Self, Error> {
+        let mut chars = expression.chars();
+
+        ensure!(
+            chars.next() == Some('s'),
+            ErrorKind::Parse,
+            "transform must be an `s` expression: `{}`",
+            expression
+        );
+
+        let delimiter = match chars.next() {
+            Some(c) if c != '\\' && c != '\n' && !c.is_alphanumeric() => c,
+            _ => fail!(
+                ErrorKind::Parse,
+                "invalid delimiter in transform: `{}`",
+                expression
+            ),
+        };
+
+        let mut parts = vec![String::new()];
+
+        while let Some(c) = chars.next() {
+            if c == delimiter {
+                parts.push(String::new());
+                continue;
+            }
+
+            let part = parts.last_mut().unwrap();
+            part.push(c);
+
+            if c == '\\' {
+                if let Some(escaped) = chars.next() {
+                    part.push(escaped);
+                }
+            }
+        }
+
+        ensure!(
+            parts.len() == 3,
+            ErrorKind::Parse,
+            "unterminated transform: `{}`",
+            expression
+        );
+
+        let mut regex = RegexBuilder::new(&unescape_pattern(&parts[0], delimiter));
+        let mut global = false;
+
+        for flag in parts[2].chars() {
+            match flag {
+                'g' => global = true,
+                'i' => {
+                    regex.case_insensitive(true);
+                }
+                _ => fail!(
+                    ErrorKind::Parse,
+                    "unknown flag `{}` in transform: `{}`",
+                    flag,
+                    expression
+                ),
+            }
+        }
+
+        let regex = regex.build().map_err(|e| {
+            format_err!(
+                ErrorKind::Parse,
+                "invalid pattern in transform `{}`: {}",
+                expression,
+                e
+            )
+        })?;
+
+        Ok(Self {
+            regex,
+            replacement: convert_replacement(&parts[1]),
+            global,
+        })
+    }
+}
+
+/// Remove the backslashes from escaped delimiters in a pattern, leaving any
+/// other escape sequences for the regex parser
+fn unescape_pattern(pattern: &str, delimiter: char) -> String {
+    let mut result = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&delimiter) {
+            chars.next();
+            result.push_str(&regex::escape(&delimiter.to_string()));
+        } else {
+            result.push(c);
+
+            // Keep escaped backslashes from escaping whatever follows them
+            if c == '\\' {
+                result.extend(chars.next());
+            }
+        }
+    }
+
+    result
+}
+
+/// Convert a sed replacement (with `&` and `\1`-style references) into the
+/// syntax of [`Regex::replace`]
+fn convert_replacement(replacement: &str) -> String {
+    let mut result = String::new();
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => result.push_str("${0}"),
+            '$' => result.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    result.push_str("${");
+                    result.push(digit);
+                    result.push('}');
+                }
+                Some('$') => result.push_str("$$"),
+                Some(escaped) => result.push(escaped),
+                None => result.push('\\'),
+            },
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
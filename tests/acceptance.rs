@@ -6,8 +6,13 @@
 
 use abscissa_core::testing::prelude::*;
 use once_cell::sync::Lazy;
-use std::fs;
-use tempfile::NamedTempFile;
+use std::{
+    fs,
+    io::Read,
+    path::Path,
+    process::{Command, Stdio},
+};
+use tempfile::{NamedTempFile, TempDir};
 
 /// Example files to build a `sear` archive with
 const FIXTURE_FILES: &[&str] = &["bar.txt", "baz.txt", "foo.txt"];
@@ -41,7 +46,7 @@ fn test_create() {
     let mut runner = RUNNER.clone();
 
     let cmd = runner
-        .args(&[
+        .args([
             "-K",
             "tests/fixtures/keys/encryption.key",
             "-C",
@@ -59,3 +64,1090 @@ fn test_create() {
     assert!(output_path.exists());
     output_path.close().unwrap();
 }
+
+#[test]
+fn test_create_and_extract() {
+    let archive_file = NamedTempFile::new().unwrap();
+    let archive_path = archive_file.path().to_str().unwrap().to_owned();
+    let output_dir = TempDir::new().unwrap();
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-C",
+            "tests/fixtures/files",
+            "-cf",
+            &archive_path,
+        ])
+        .args(FIXTURE_FILES)
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-C",
+            output_dir.path().to_str().unwrap(),
+            "-xf",
+            &archive_path,
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    for file in FIXTURE_FILES {
+        let expected_path = format!("tests/fixtures/files/{}", file);
+        let actual_path = output_dir.path().join(file);
+
+        let expected = fs::read(&expected_path).unwrap();
+        let actual = fs::read(&actual_path).unwrap();
+        assert_eq!(expected, actual);
+
+        let expected_mtime = fs::metadata(&expected_path).unwrap().modified().unwrap();
+        let actual_mtime = fs::metadata(&actual_path).unwrap().modified().unwrap();
+        assert_eq!(expected_mtime, actual_mtime);
+    }
+}
+
+/// Pipe an archive written to stdout directly into extraction from stdin,
+/// whether its index is in the header (so it's streamed) or at the end (so
+/// it's spooled first)
+#[test]
+fn test_create_and_extract_through_pipe() {
+    for create_args in &[&["--index-first", "-cvf", "-"][..], &["-cvf", "-"][..]] {
+        let output_dir = TempDir::new().unwrap();
+
+        let mut create = Command::new(env!("CARGO_BIN_EXE_sear"))
+            .args([
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "-C",
+                "tests/fixtures/files",
+            ])
+            .args(*create_args)
+            .args(FIXTURE_FILES)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let extract = Command::new(env!("CARGO_BIN_EXE_sear"))
+            .args([
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "-C",
+                output_dir.path().to_str().unwrap(),
+                "-xf",
+                "-",
+            ])
+            .stdin(create.stdout.take().unwrap())
+            .status()
+            .unwrap();
+
+        assert!(create.wait().unwrap().success());
+        assert!(extract.success(), "{:?}", create_args);
+
+        for file in FIXTURE_FILES {
+            let expected = fs::read(format!("tests/fixtures/files/{}", file)).unwrap();
+            let actual = fs::read(output_dir.path().join(file)).unwrap();
+            assert_eq!(expected, actual);
+        }
+    }
+}
+
+/// `-d` reports files which differ from the archive, including files whose
+/// contents changed without their size or modification time changing
+#[test]
+fn test_diff() {
+    let input_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+    let archive_path = archive_file.path().to_str().unwrap();
+
+    for file in FIXTURE_FILES {
+        fs::copy(
+            format!("tests/fixtures/files/{}", file),
+            input_dir.path().join(file),
+        )
+        .unwrap();
+    }
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-C",
+            input_dir.path().to_str().unwrap(),
+            "-cf",
+            archive_path,
+        ])
+        .args(FIXTURE_FILES)
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let diff = || {
+        Command::new(env!("CARGO_BIN_EXE_sear"))
+            .args([
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "-C",
+                input_dir.path().to_str().unwrap(),
+                "--format",
+                "json",
+                "-df",
+                archive_path,
+            ])
+            .output()
+            .unwrap()
+    };
+
+    let output = diff();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    fs::remove_file(input_dir.path().join("bar.txt")).unwrap();
+
+    let baz_path = input_dir.path().join("baz.txt");
+    let modified = fs::metadata(&baz_path).unwrap().modified().unwrap();
+    let mut contents = fs::read(&baz_path).unwrap();
+    contents[0] ^= 1;
+    fs::write(&baz_path, &contents).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&baz_path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+
+    let output = diff();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(
+            r#"{"differences":["missing"],"path":"bar.txt"}"#,
+            "\n",
+            r#"{"differences":["contents"],"path":"baz.txt"}"#,
+            "\n"
+        )
+    );
+}
+
+/// `--format json` reports paths which aren't valid UTF-8 losslessly
+#[cfg(unix)]
+#[test]
+fn test_diff_non_utf8_path() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let input_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+    let archive_path = archive_file.path().to_str().unwrap();
+    let latin1_name = OsStr::from_bytes(b"caf\xe9.txt");
+
+    fs::write(input_dir.path().join(latin1_name), b"latin1\n").unwrap();
+
+    // Command-line arguments must be valid Unicode, so list the file instead
+    let list_file = NamedTempFile::new().unwrap();
+    fs::write(list_file.path(), b"caf\xe9.txt\n").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_sear"))
+        .args(["-K", "tests/fixtures/keys/encryption.key", "-C"])
+        .arg(input_dir.path())
+        .args(["-cf", archive_path, "-T"])
+        .arg(list_file.path())
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    fs::remove_file(input_dir.path().join(latin1_name)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sear"))
+        .args(["-K", "tests/fixtures/keys/encryption.key", "-C"])
+        .arg(input_dir.path())
+        .args(["--format", "json", "-df", archive_path])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(
+            r#"{"differences":["missing"],"path":"#,
+            "\"caf\u{fffd}.txt\",",
+            r#""path_bytes":[99,97,102,233,46,116,120,116]}"#,
+            "\n"
+        )
+    );
+}
+
+/// `sear diff` compares the indexes of two archives, even when they're
+/// encrypted under different keys
+#[test]
+fn test_diff_against_archive() {
+    let input_dir = TempDir::new().unwrap();
+    let archives = [NamedTempFile::new().unwrap(), NamedTempFile::new().unwrap()];
+    let archive_paths = archives
+        .iter()
+        .map(|archive| archive.path().to_str().unwrap())
+        .collect::<Vec<_>>();
+
+    for file in FIXTURE_FILES {
+        fs::copy(
+            format!("tests/fixtures/files/{}", file),
+            input_dir.path().join(file),
+        )
+        .unwrap();
+    }
+
+    let create = |key: &str, archive_path: &str, files: &[&str]| {
+        let mut runner = RUNNER.clone();
+
+        runner
+            .args([
+                "-K",
+                key,
+                "-C",
+                input_dir.path().to_str().unwrap(),
+                "-cf",
+                archive_path,
+            ])
+            .args(files)
+            .run()
+            .wait()
+            .unwrap()
+            .expect_success();
+    };
+
+    create(
+        "tests/fixtures/keys/encryption.key",
+        archive_paths[0],
+        &["bar.txt", "foo.txt"],
+    );
+
+    fs::write(input_dir.path().join("foo.txt"), b"updated contents\n").unwrap();
+
+    create(
+        "tests/fixtures/keys/other.key",
+        archive_paths[1],
+        &["baz.txt", "foo.txt"],
+    );
+
+    let diff = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_sear"))
+            .args([
+                "diff",
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "--new-key",
+                "tests/fixtures/keys/other.key",
+                "--format",
+                "json",
+            ])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = diff(&[archive_paths[0], archive_paths[1]]);
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(
+            r#"{"differences":["missing"],"path":"bar.txt"}"#,
+            "\n",
+            r#"{"differences":["added"],"path":"baz.txt"}"#,
+            "\n",
+            r#"{"differences":["size","mtime"],"path":"foo.txt"}"#,
+            "\n"
+        )
+    );
+
+    // Paths following the archives select which entries are compared
+    let output = diff(&[archive_paths[0], archive_paths[1], "foo.txt"]);
+
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        concat!(r#"{"differences":["size","mtime"],"path":"foo.txt"}"#, "\n")
+    );
+}
+
+/// Files can be excluded with `--exclude`, `--exclude-from`, and per-directory
+/// `.searignore` files, all with gitignore semantics
+#[test]
+fn test_exclude() {
+    let input_dir = TempDir::new().unwrap();
+    let patterns_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    for file in &[
+        "build/out.o",
+        "node_modules/pkg/index.js",
+        "src/build/gen.rs",
+        "src/debug.log",
+        "src/keep.log",
+        "src/main.rs",
+        "target/debug/sear",
+    ] {
+        let path = input_dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"contents\n").unwrap();
+    }
+
+    fs::write(
+        input_dir.path().join("src/.searignore"),
+        b"*.log\n!keep.log\n",
+    )
+    .unwrap();
+
+    // Anchored, so only the top-level `build` directory is excluded
+    let patterns_file = patterns_dir.path().join("patterns");
+    fs::write(&patterns_file, b"/build\n").unwrap();
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "--exclude",
+            "target/",
+            "--exclude",
+            "node_modules/",
+            "--exclude-from",
+            patterns_file.to_str().unwrap(),
+            "-C",
+            input_dir.path().to_str().unwrap(),
+            "-cf",
+            archive_file.path().to_str().unwrap(),
+            ".",
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archive_file.path()).unwrap();
+    let archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+
+    let paths = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.path.to_str().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        paths,
+        [
+            "src",
+            "src/.searignore",
+            "src/build",
+            "src/build/gen.rs",
+            "src/keep.log",
+            "src/main.rs"
+        ]
+    );
+}
+
+/// `-T - --null` reads NUL-separated names from stdin, which can contain
+/// newlines, alongside positional arguments (in command-line order)
+#[cfg(unix)]
+#[test]
+fn test_files_from_stdin() {
+    use std::io::Write;
+
+    let input_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    for file in &["bar.txt", "foo.txt", "line\nbreak.txt"] {
+        fs::write(input_dir.path().join(file), b"contents\n").unwrap();
+    }
+
+    let mut create = Command::new(env!("CARGO_BIN_EXE_sear"))
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-C",
+            input_dir.path().to_str().unwrap(),
+            "-T",
+            "-",
+            "--null",
+            "-cf",
+            archive_file.path().to_str().unwrap(),
+            "bar.txt",
+        ])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    create
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"line\nbreak.txt\0foo.txt\0")
+        .unwrap();
+
+    assert!(create.wait().unwrap().success());
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archive_file.path()).unwrap();
+    let archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+
+    let paths = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.path.to_str().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(paths, ["line\nbreak.txt", "foo.txt", "bar.txt"]);
+}
+
+/// Only changed files are appended by `-u`, with later versions shadowing
+/// earlier ones on extraction
+#[test]
+fn test_update() {
+    let input_dir = TempDir::new().unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+    let archive_path = archive_file.path().to_str().unwrap().to_owned();
+
+    for file in FIXTURE_FILES {
+        fs::copy(
+            format!("tests/fixtures/files/{}", file),
+            input_dir.path().join(file),
+        )
+        .unwrap();
+    }
+
+    let sear = |op: &str| {
+        let mut runner = RUNNER.clone();
+
+        runner
+            .args([
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "-C",
+                input_dir.path().to_str().unwrap(),
+                op,
+                &archive_path,
+            ])
+            .args(FIXTURE_FILES)
+            .run()
+            .wait()
+            .unwrap()
+            .expect_success();
+    };
+
+    let entry_count = || {
+        let keyring = example_keyring();
+        let archive = fs::File::open(&archive_path).unwrap();
+        sear::Archive::open(archive, keyring.symmetric_key().unwrap())
+            .unwrap()
+            .entries()
+            .len()
+    };
+
+    sear("-cf");
+    assert_eq!(entry_count(), FIXTURE_FILES.len());
+
+    // Nothing has changed, so nothing should be appended
+    sear("-uf");
+    assert_eq!(entry_count(), FIXTURE_FILES.len());
+
+    fs::write(input_dir.path().join("foo.txt"), b"updated contents\n").unwrap();
+    sear("-uf");
+    assert_eq!(entry_count(), FIXTURE_FILES.len() + 1);
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-C",
+            output_dir.path().to_str().unwrap(),
+            "-xf",
+            &archive_path,
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    assert_eq!(
+        fs::read(output_dir.path().join("foo.txt")).unwrap(),
+        b"updated contents\n"
+    );
+}
+
+/// A failed `-r` leaves the archive as it was
+#[test]
+fn test_append_missing_file() {
+    let archive_dir = TempDir::new().unwrap();
+    let archive_path = archive_dir.path().join("files.sear");
+    let archive_path = archive_path.to_str().unwrap();
+
+    let sear = |op: &str, file: &str, code: i32| {
+        let mut runner = RUNNER.clone();
+
+        runner
+            .args([
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "-C",
+                "tests/fixtures/files",
+                op,
+                archive_path,
+                file,
+            ])
+            .run()
+            .wait()
+            .unwrap()
+            .expect_code(code);
+    };
+
+    sear("-cf", "foo.txt", 0);
+    let original = fs::read(archive_path).unwrap();
+
+    sear("-rf", "nonexistent.txt", 1);
+    assert_eq!(fs::read(archive_path).unwrap(), original);
+    assert_eq!(fs::read_dir(archive_dir.path()).unwrap().count(), 1);
+
+    sear("-rf", "bar.txt", 0);
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archive_path).unwrap();
+    let archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+    assert_eq!(archive.entries().len(), 2);
+    assert_eq!(fs::read_dir(archive_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_listed_incremental() {
+    let input_dir = TempDir::new().unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let snapshot_dir = TempDir::new().unwrap();
+    let snapshot_file = snapshot_dir.path().join("backup.snar");
+    let data_dir = input_dir.path().join("data");
+    fs::create_dir_all(data_dir.join("old")).unwrap();
+    fs::write(data_dir.join("old").join("stale.txt"), b"stale\n").unwrap();
+
+    for file in FIXTURE_FILES {
+        fs::copy(
+            format!("tests/fixtures/files/{}", file),
+            data_dir.join(file),
+        )
+        .unwrap();
+    }
+
+    let archives = [NamedTempFile::new().unwrap(), NamedTempFile::new().unwrap()];
+
+    let sear = |dir: &Path, args: &[&str]| {
+        let mut runner = RUNNER.clone();
+
+        runner
+            .args([
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "-C",
+                dir.to_str().unwrap(),
+            ])
+            .args(args)
+            .run()
+            .wait()
+            .unwrap()
+            .expect_success();
+    };
+
+    let incremental = |archive: &NamedTempFile| {
+        sear(
+            input_dir.path(),
+            &[
+                "-g",
+                snapshot_file.to_str().unwrap(),
+                "-cf",
+                archive.path().to_str().unwrap(),
+                "data",
+            ],
+        );
+    };
+
+    // Level 0: no snapshot exists yet, so everything is archived
+    incremental(&archives[0]);
+
+    fs::write(data_dir.join("foo.txt"), b"updated contents\n").unwrap();
+    fs::write(data_dir.join("new.txt"), b"new\n").unwrap();
+    fs::remove_file(data_dir.join("bar.txt")).unwrap();
+    fs::remove_dir_all(data_dir.join("old")).unwrap();
+
+    // Level 1: only changes since level 0 are archived
+    incremental(&archives[1]);
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archives[1].path()).unwrap();
+    let archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+
+    let entries = archive
+        .entries()
+        .iter()
+        .map(|entry| {
+            let deleted = entry.kind == sear::entry::Kind::Deleted;
+            (entry.path.to_str().unwrap(), deleted)
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        entries,
+        [
+            ("data/old/stale.txt", true),
+            ("data/old", true),
+            ("data/bar.txt", true),
+            ("data", false),
+            ("data/foo.txt", false),
+            ("data/new.txt", false),
+        ]
+    );
+
+    // Restoring the chain in order reproduces the final tree
+    for archive in &archives {
+        sear(
+            output_dir.path(),
+            &["-xf", archive.path().to_str().unwrap()],
+        );
+    }
+
+    let restored = output_dir.path().join("data");
+    let mut files = fs::read_dir(&restored)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+
+    files.sort();
+    assert_eq!(files, ["baz.txt", "foo.txt", "new.txt"]);
+    assert_eq!(
+        fs::read(restored.join("foo.txt")).unwrap(),
+        b"updated contents\n"
+    );
+}
+
+/// Load the example encryption key
+fn example_keyring() -> sear::KeyRing {
+    let mut keyring = sear::KeyRing::new();
+    keyring
+        .load_symmetric_key("tests/fixtures/keys/encryption.key")
+        .unwrap();
+    keyring
+}
+
+#[cfg(unix)]
+#[test]
+fn test_extract_preserve_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let input_dir = TempDir::new().unwrap();
+    let readonly_dir = input_dir.path().join("ro");
+    fs::create_dir(&readonly_dir).unwrap();
+    fs::write(readonly_dir.join("file.txt"), b"hello\n").unwrap();
+    fs::set_permissions(&readonly_dir, fs::Permissions::from_mode(0o555)).unwrap();
+
+    let archive_file = NamedTempFile::new().unwrap();
+    let archive_path = archive_file.path().to_str().unwrap().to_owned();
+    let output_dir = TempDir::new().unwrap();
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-C",
+            input_dir.path().to_str().unwrap(),
+            "-cf",
+            &archive_path,
+            "ro",
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-C",
+            output_dir.path().to_str().unwrap(),
+            "-xpf",
+            &archive_path,
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let extracted_dir = output_dir.path().join("ro");
+    let mode = fs::metadata(&extracted_dir).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o555);
+    assert_eq!(
+        fs::read(extracted_dir.join("file.txt")).unwrap(),
+        b"hello\n"
+    );
+
+    // Allow the temporary directories to be cleaned up
+    for dir in [&readonly_dir, &extracted_dir] {
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+/// `--transform` rewrites paths when creating archives, and
+/// `--strip-components` removes their leading components when extracting
+#[test]
+fn test_transform_paths() {
+    let input_dir = TempDir::new().unwrap();
+    let output_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    fs::create_dir(input_dir.path().join("bin")).unwrap();
+    fs::write(input_dir.path().join("bin/sear"), b"binary\n").unwrap();
+    fs::write(input_dir.path().join("README.md"), b"readme\n").unwrap();
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "--transform",
+            "s,^,sear-1.0/,",
+            "--transform",
+            "s/readme/LICENSE/i",
+            "-C",
+            input_dir.path().to_str().unwrap(),
+            "-cf",
+            archive_file.path().to_str().unwrap(),
+            "README.md",
+            "bin",
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archive_file.path()).unwrap();
+    let archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+
+    let paths = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.path.to_str().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        paths,
+        ["sear-1.0/LICENSE.md", "sear-1.0/bin", "sear-1.0/bin/sear"]
+    );
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "--strip-components",
+            "1",
+            "-C",
+            output_dir.path().to_str().unwrap(),
+            "-xf",
+            archive_file.path().to_str().unwrap(),
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    assert_eq!(
+        fs::read(output_dir.path().join("LICENSE.md")).unwrap(),
+        b"readme\n"
+    );
+    assert_eq!(
+        fs::read(output_dir.path().join("bin/sear")).unwrap(),
+        b"binary\n"
+    );
+    assert!(!output_dir.path().join("sear-1.0").exists());
+}
+
+/// Paths rewritten by `--transform` may not escape the archive root, whether
+/// by becoming absolute (without `-P`) or by gaining `..` components
+#[test]
+fn test_transform_rejects_unsafe_paths() {
+    let input_dir = TempDir::new().unwrap();
+    fs::write(input_dir.path().join("passwd"), b"root\n").unwrap();
+
+    for transform in &["s,^,/etc/,", "s,^,../,"] {
+        let archive_file = NamedTempFile::new().unwrap();
+        let mut runner = RUNNER.clone();
+
+        runner
+            .args([
+                "-K",
+                "tests/fixtures/keys/encryption.key",
+                "--transform",
+                transform,
+                "-C",
+                input_dir.path().to_str().unwrap(),
+                "-cf",
+                archive_file.path().to_str().unwrap(),
+                "passwd",
+            ])
+            .run()
+            .wait()
+            .unwrap()
+            .expect_code(1);
+    }
+}
+
+/// Each `-C` option applies to the files following it, and is relative to
+/// the directory given by the previous one (ala `tar`)
+#[test]
+fn test_chdir_per_file() {
+    let root_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    for (file, contents) in &[("bin/sear", "binary\n"), ("docs/README.md", "readme\n")] {
+        let path = root_dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-cf",
+            archive_file.path().to_str().unwrap(),
+            "-C",
+            root_dir.path().join("bin").to_str().unwrap(),
+            "sear",
+            "-C",
+            "../docs",
+            "README.md",
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archive_file.path()).unwrap();
+    let mut archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+
+    let paths = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.path.to_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+
+    assert_eq!(paths, ["sear", "README.md"]);
+
+    let mut contents = String::new();
+    archive
+        .read_entry(1)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+
+    assert_eq!(contents, "readme\n");
+}
+
+/// The order of `-C`, `-T`, and file arguments is kept regardless of how
+/// they're spelled or which other options are interleaved with them
+#[test]
+fn test_chdir_option_order() {
+    let root_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+    let list_file = NamedTempFile::new().unwrap();
+
+    let files = [
+        ("bin/sear", "binary\n"),
+        ("bin/tool", "tool\n"),
+        ("bin/helper", "helper\n"),
+        ("docs/README.md", "readme\n"),
+    ];
+
+    for (file, contents) in &files {
+        let path = root_dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fs::write(list_file.path(), "tool\n").unwrap();
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "-K",
+            "tests/fixtures/keys/encryption.key",
+            "-cf",
+            archive_file.path().to_str().unwrap(),
+            "--exclude",
+            "*.bak",
+            "-C",
+            root_dir.path().join("bin").to_str().unwrap(),
+            "sear",
+            "-T",
+            list_file.path().to_str().unwrap(),
+            "--chdir=../docs",
+            "--threads",
+            "2",
+            "README.md",
+            "-vC",
+            "../bin",
+            "helper",
+        ])
+        .capture_stdout()
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archive_file.path()).unwrap();
+    let mut archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+
+    let paths = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.path.to_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+
+    assert_eq!(paths, ["sear", "tool", "README.md", "helper"]);
+
+    for (i, (_, expected)) in [files[0], files[1], files[3], files[2]].iter().enumerate() {
+        let mut contents = String::new();
+        archive
+            .read_entry(i)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+
+        assert_eq!(&contents, expected);
+    }
+}
+
+/// Settings are read from the profile selected in the configuration file,
+/// with key paths relative to the file, and command-line flags taking
+/// precedence
+#[test]
+fn test_config_profile() {
+    let config_dir = TempDir::new().unwrap();
+    let input_dir = TempDir::new().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    fs::create_dir(config_dir.path().join("keys")).unwrap();
+    fs::copy(
+        "tests/fixtures/keys/encryption.key",
+        config_dir.path().join("keys/backup.key"),
+    )
+    .unwrap();
+
+    let config_file = config_dir.path().join("sear.toml");
+    fs::write(
+        &config_file,
+        "[defaults]\n\
+         exclude = [\"*.log\"]\n\
+         \n\
+         [profiles.backup]\n\
+         encryption_key = \"keys/backup.key\"\n\
+         chunk_size = 1024\n",
+    )
+    .unwrap();
+
+    for file in &["data.db", "debug.log", "keep.log"] {
+        fs::write(input_dir.path().join(file), b"contents\n").unwrap();
+    }
+
+    let mut runner = RUNNER.clone();
+
+    runner
+        .args([
+            "--config",
+            config_file.to_str().unwrap(),
+            "--profile",
+            "backup",
+            "--exclude",
+            "!keep.log",
+            "-C",
+            input_dir.path().to_str().unwrap(),
+            "-cf",
+            archive_file.path().to_str().unwrap(),
+            ".",
+        ])
+        .run()
+        .wait()
+        .unwrap()
+        .expect_success();
+
+    let keyring = example_keyring();
+    let archive = fs::File::open(archive_file.path()).unwrap();
+    let archive = sear::Archive::open(archive, keyring.symmetric_key().unwrap()).unwrap();
+
+    let paths = archive
+        .entries()
+        .iter()
+        .map(|entry| entry.path.to_str().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(paths, ["data.db", "keep.log"]);
+
+    let mut runner = RUNNER.clone();
+    let status = runner
+        .args([
+            "--config",
+            config_file.to_str().unwrap(),
+            "--profile",
+            "missing",
+            "-tf",
+            archive_file.path().to_str().unwrap(),
+        ])
+        .capture_stderr()
+        .status();
+
+    assert_eq!(status.code(), 1);
+
+    // Settings which aren't supported yet are rejected by name
+    fs::write(&config_file, "[profiles.backup]\ncompression = \"zstd\"\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_sear"))
+        .args([
+            "--config",
+            config_file.to_str().unwrap(),
+            "-tf",
+            archive_file.path().to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("`compression` isn't supported yet"));
+}
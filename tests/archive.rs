@@ -0,0 +1,453 @@
+//! Tests for building and reading archives with the library API
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs, rust_2018_idioms, unused_qualifications)]
+
+use sear::{
+    archive,
+    crypto::stream::writer::{ChunkSize, MAX_THREADS},
+    entry::{attributes::ContentDetection, Digest},
+    error::ErrorKind,
+    exclude::Exclude,
+    Archive, Builder, Entry, KeyRing, StreamingArchive,
+};
+use std::{
+    fs::{self, File},
+    io::{Cursor, Read},
+    path::Path,
+};
+use tempfile::NamedTempFile;
+
+/// Example files to build archives with
+const FIXTURE_FILES: &[&str] = &["bar.txt", "baz.txt", "foo.txt"];
+
+/// Load the example encryption key
+fn example_keyring() -> KeyRing {
+    let mut keyring = KeyRing::new();
+    keyring
+        .load_symmetric_key("tests/fixtures/keys/encryption.key")
+        .unwrap();
+    keyring
+}
+
+#[test]
+fn record_creator() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    let mut builder =
+        Builder::new(archive_file.reopen().unwrap(), key, Default::default()).unwrap();
+    builder.username(Some("auditor".to_owned())).host(None);
+    builder.finish().unwrap();
+
+    let archive = Archive::open(File::open(archive_file.path()).unwrap(), key).unwrap();
+    assert_eq!(archive.username(), Some("auditor"));
+    assert_eq!(archive.host(), None);
+    assert!(archive.created_at().is_some());
+    assert!(archive.entries().is_empty());
+}
+
+#[test]
+fn record_digests() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    let mut builder =
+        Builder::new(archive_file.reopen().unwrap(), key, Default::default()).unwrap();
+
+    for file in FIXTURE_FILES {
+        let path = Path::new("tests/fixtures/files").join(file);
+        let entry = Entry::for_file(&path).unwrap();
+        builder.append(entry, File::open(&path).unwrap()).unwrap();
+    }
+
+    builder.finish().unwrap();
+
+    let archive = Archive::open(File::open(archive_file.path()).unwrap(), key).unwrap();
+
+    for entry in archive.entries() {
+        let expected = Digest::for_reader(File::open(&entry.path).unwrap()).unwrap();
+        assert_eq!(entry.digest, Some(expected));
+    }
+}
+
+#[test]
+fn filter_entries() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    let mut exclude = Exclude::new();
+    exclude.add_patterns(["ba?.txt"]).unwrap();
+
+    let mut builder =
+        Builder::new(archive_file.reopen().unwrap(), key, Default::default()).unwrap();
+    builder.filter(move |entry| !exclude.is_excluded(&entry.path, false));
+
+    for file in FIXTURE_FILES {
+        let path = Path::new("tests/fixtures/files").join(file);
+        let entry = Entry::for_file(&path).unwrap();
+        builder.append(entry, File::open(&path).unwrap()).unwrap();
+    }
+
+    builder.finish().unwrap();
+
+    let archive = Archive::open(File::open(archive_file.path()).unwrap(), key).unwrap();
+    assert_eq!(archive.entries().len(), 1);
+    assert_eq!(
+        archive.entries()[0].path,
+        Path::new("tests/fixtures/files/foo.txt")
+    );
+}
+
+#[test]
+fn index_in_header() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    let mut entries = vec![];
+
+    for file in &["bar.txt", "foo.txt"] {
+        let mut entry = Entry::for_file(format!("tests/fixtures/files/{}", file)).unwrap();
+        entry.path = file.into();
+        entries.push(entry);
+    }
+
+    let mut builder =
+        Builder::new(archive_file.reopen().unwrap(), key, Default::default()).unwrap();
+    builder.index_in_header(entries.clone()).unwrap();
+
+    // Entries must be appended in the same order as the index
+    let contents = File::open("tests/fixtures/files/foo.txt").unwrap();
+    assert!(builder.append(entries[1].clone(), contents).is_err());
+
+    for entry in &entries {
+        let contents = File::open(Path::new("tests/fixtures/files").join(&entry.path)).unwrap();
+        builder.append(entry.clone(), contents).unwrap();
+    }
+
+    builder.finish().unwrap();
+
+    let mut archive = Archive::open(File::open(archive_file.path()).unwrap(), key).unwrap();
+    assert!(archive.has_index_in_header());
+    assert_eq!(archive.entries(), entries.as_slice());
+
+    for (index, entry) in entries.iter().enumerate() {
+        let mut contents = vec![];
+        archive
+            .read_entry(index)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+
+        let expected = fs::read(Path::new("tests/fixtures/files").join(&entry.path)).unwrap();
+        assert_eq!(contents, expected);
+    }
+}
+
+/// The last entry can end exactly on a chunk boundary, in which case the
+/// final chunk must still be encrypted as the last block
+#[test]
+fn index_in_header_chunk_boundary() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+
+    // The index precedes the contents in the stream, so one of these lengths
+    // ends the stream on a chunk boundary whatever the index's length is
+    for length in 0..=ChunkSize::Kib1 as usize {
+        let data = vec![0x17; length];
+        let mut entry = Entry::for_file("tests/fixtures/files/foo.txt").unwrap();
+        entry.path = "entry".into();
+        entry.length = length as u64;
+
+        let mut archive = vec![];
+        let mut builder = Builder::new(&mut archive, key, ChunkSize::Kib1).unwrap();
+        builder.content_detection(ContentDetection::Disabled);
+        builder.index_in_header(vec![entry.clone()]).unwrap();
+        builder.append(entry, data.as_slice()).unwrap();
+        builder.finish().unwrap();
+
+        let mut archive = Archive::open(Cursor::new(archive), key).unwrap();
+        let mut contents = vec![];
+        archive
+            .read_entry(0)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+
+        assert_eq!(contents, data);
+    }
+}
+
+#[test]
+fn stream_archive_without_seeking() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+
+    // Find the length of the last entry at which the plaintext ends exactly
+    // on an encrypted chunk boundary (the next byte adds a whole new chunk)
+    let boundary = (1..=ChunkSize::Kib1 as usize)
+        .find(|&length| {
+            let (archive, _) = build_streamable(key, length);
+            archive.len() >= build_streamable(key, length - 1).0.len() + 16
+        })
+        .unwrap()
+        - 1;
+
+    for length in &[0, boundary - 1, boundary, boundary + 1] {
+        let (archive, contents) = build_streamable(key, *length);
+        let mut streaming = StreamingArchive::open(archive.as_slice(), key).unwrap();
+        assert_eq!(streaming.entries().len(), contents.len());
+
+        // Leave the first entry unread, which should be skipped
+        let (entry, _) = streaming.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, Path::new("entry0"));
+
+        let mut data = vec![];
+        {
+            let (entry, mut reader) = streaming.next_entry().unwrap().unwrap();
+            assert_eq!(entry.path, Path::new("entry1"));
+            reader.read_to_end(&mut data).unwrap();
+        }
+        assert_eq!(data, contents[1]);
+        assert!(streaming.next_entry().unwrap().is_none());
+
+        // Truncated archives must not be read successfully
+        let mut truncated = StreamingArchive::open(&archive[..archive.len() - 1], key).unwrap();
+        while let Ok(Some(_)) = truncated.next_entry() {}
+        assert!(truncated.next_entry().is_err());
+    }
+}
+
+/// Build an archive with its index in the header containing two entries,
+/// the last of which is `length` bytes long
+fn build_streamable(key: &sear::crypto::kdf::Key, length: usize) -> (Vec<u8>, [Vec<u8>; 2]) {
+    let contents = [vec![0x42; 3000], vec![0x17; length]];
+    let mut entries = vec![];
+
+    for (index, data) in contents.iter().enumerate() {
+        let mut entry = Entry::for_stream(format!("entry{}", index)).unwrap();
+        entry.length = data.len() as u64;
+        entries.push(entry);
+    }
+
+    let mut archive = vec![];
+    let mut builder = Builder::new(&mut archive, key, ChunkSize::Kib1).unwrap();
+    builder.content_detection(ContentDetection::Disabled);
+    builder.index_in_header(entries.clone()).unwrap();
+
+    for (entry, data) in entries.iter().zip(&contents) {
+        builder.append(entry.clone(), data.as_slice()).unwrap();
+    }
+
+    builder.finish().unwrap();
+    (archive, contents)
+}
+
+#[test]
+fn stream_archive_requires_index_in_header() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+
+    let mut archive = vec![];
+    Builder::new(&mut archive, key, Default::default())
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    assert!(StreamingArchive::open(archive.as_slice(), key).is_err());
+}
+
+#[test]
+fn append_to_archive() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    // Entries spanning several chunks, appended a few at a time
+    let contents: Vec<Vec<u8>> = (0..6).map(|i| vec![i as u8; 700 * i]).collect();
+
+    let mut builder = Builder::new(archive_file.reopen().unwrap(), key, ChunkSize::Kib1).unwrap();
+    builder.username(Some("auditor".to_owned()));
+    builder
+        .append(Entry::for_stream("entry0").unwrap(), contents[0].as_slice())
+        .unwrap();
+    builder.finish().unwrap();
+
+    let created_at = Archive::open(File::open(archive_file.path()).unwrap(), key)
+        .unwrap()
+        .created_at();
+
+    for batch in contents[1..].chunks(2) {
+        let appended = Archive::open(File::open(archive_file.path()).unwrap(), key)
+            .unwrap()
+            .entries()
+            .len();
+
+        let mut builder = Builder::reopen(archive_file.reopen().unwrap(), key).unwrap();
+
+        for (index, data) in batch.iter().enumerate() {
+            let entry = Entry::for_stream(format!("entry{}", appended + index)).unwrap();
+            builder.append(entry, data.as_slice()).unwrap();
+        }
+
+        builder.finish().unwrap();
+    }
+
+    let mut archive = Archive::open(File::open(archive_file.path()).unwrap(), key).unwrap();
+    assert_eq!(archive.entries().len(), contents.len());
+    assert_eq!(archive.username(), Some("auditor"));
+    assert_eq!(archive.created_at(), created_at);
+
+    for (index, expected) in contents.iter().enumerate() {
+        assert_eq!(
+            archive.entries()[index].path,
+            Path::new(&format!("entry{}", index))
+        );
+
+        let mut data = vec![];
+        archive
+            .read_entry(index)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+
+        assert_eq!(&data, expected);
+    }
+}
+
+#[test]
+fn encrypt_with_threads() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    // Entries spanning many chunks, so several are in flight at once
+    let contents: Vec<Vec<u8>> = (0..4)
+        .map(|i| {
+            (0..40_000 + i * 1000)
+                .map(|n| (n * (i + 1)) as u8)
+                .collect()
+        })
+        .collect();
+
+    let mut builder = Builder::new(archive_file.reopen().unwrap(), key, ChunkSize::Kib1).unwrap();
+    assert!(builder.threads(0).is_err());
+    assert!(builder.threads(MAX_THREADS + 1).is_err());
+    builder.threads(4).unwrap();
+
+    for (index, data) in contents[..2].iter().enumerate() {
+        let entry = Entry::for_stream(format!("entry{}", index)).unwrap();
+        builder.append(entry, data.as_slice()).unwrap();
+    }
+
+    builder.finish().unwrap();
+
+    // Appending resumes the stream partway through a chunk
+    let mut builder = Builder::reopen(archive_file.reopen().unwrap(), key).unwrap();
+    builder.threads(3).unwrap();
+
+    for (index, data) in contents[2..].iter().enumerate() {
+        let entry = Entry::for_stream(format!("entry{}", index + 2)).unwrap();
+        builder.append(entry, data.as_slice()).unwrap();
+    }
+
+    builder.finish().unwrap();
+
+    let mut archive = Archive::open(File::open(archive_file.path()).unwrap(), key).unwrap();
+    assert_eq!(archive.entries().len(), contents.len());
+
+    for (index, expected) in contents.iter().enumerate() {
+        let mut data = vec![];
+        archive
+            .read_entry(index)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+
+        assert_eq!(&data, expected);
+    }
+}
+
+#[test]
+fn rewrite_without_entries() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+    let rewritten_file = NamedTempFile::new().unwrap();
+
+    let mut builder =
+        Builder::new(archive_file.reopen().unwrap(), key, Default::default()).unwrap();
+
+    for file in FIXTURE_FILES {
+        let mut entry = Entry::for_file(Path::new("tests/fixtures/files").join(file)).unwrap();
+        entry.path = file.into();
+        let contents = File::open(Path::new("tests/fixtures/files").join(file)).unwrap();
+        builder.append(entry, contents).unwrap();
+    }
+
+    builder.finish().unwrap();
+
+    let mut archive = Archive::open(File::open(archive_file.path()).unwrap(), key).unwrap();
+    archive
+        .rewrite(rewritten_file.reopen().unwrap(), key, |entry| {
+            entry.path != Path::new("baz.txt")
+        })
+        .unwrap();
+
+    let mut rewritten = Archive::open(File::open(rewritten_file.path()).unwrap(), key).unwrap();
+    assert_ne!(rewritten.uuid(), archive.uuid());
+    assert_eq!(rewritten.created_at(), archive.created_at());
+
+    let expected: Vec<_> = archive
+        .entries()
+        .iter()
+        .filter(|entry| entry.path != Path::new("baz.txt"))
+        .cloned()
+        .collect();
+
+    assert_eq!(rewritten.entries(), expected.as_slice());
+
+    for (index, entry) in expected.iter().enumerate() {
+        let mut contents = vec![];
+        rewritten
+            .read_entry(index)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+
+        let expected = fs::read(Path::new("tests/fixtures/files").join(&entry.path)).unwrap();
+        assert_eq!(contents, expected);
+    }
+}
+
+#[test]
+fn truncated_stream() {
+    let keyring = example_keyring();
+    let key = keyring.symmetric_key().unwrap();
+    let archive_file = NamedTempFile::new().unwrap();
+
+    Builder::new(archive_file.reopen().unwrap(), key, Default::default())
+        .unwrap()
+        .finish()
+        .unwrap();
+
+    // Replace the encrypted stream between the header and footer with a
+    // few bytes, which is shorter than even a MAC tag
+    let bytes = fs::read(archive_file.path()).unwrap();
+    let (head, _) = archive::read_head(&mut bytes.as_slice()).unwrap();
+    let footer_len = u16::from_le_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]);
+    let footer_start = bytes.len() - 2 - usize::from(footer_len);
+
+    for stream_len in 1..=3 {
+        let mut truncated = head.clone();
+        truncated.extend_from_slice(&vec![0u8; stream_len]);
+        truncated.extend_from_slice(&bytes[footer_start..]);
+
+        let err = Archive::open(Cursor::new(truncated), key).err().unwrap();
+        assert_eq!(*err.kind(), ErrorKind::Parse);
+    }
+}
@@ -0,0 +1,91 @@
+//! Tests for archive entries
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs, rust_2018_idioms, unused_qualifications)]
+
+use sear::{
+    diff::{self, Difference},
+    entry::{attributes::ContentDetection, normalize_path, Kind},
+    protos, Entry,
+};
+use std::{convert::TryFrom, path::Path};
+
+#[test]
+fn normalize_paths() {
+    let example_paths = [
+        ("foo/bar.txt", "foo/bar.txt"),
+        ("./foo//./bar.txt", "foo/bar.txt"),
+        ("/foo/bar.txt", "foo/bar.txt"),
+        ("//foo/bar/", "foo/bar"),
+        (".", ""),
+    ];
+
+    for (path, expected) in &example_paths {
+        let normalized = normalize_path(Path::new(path), false).unwrap();
+        assert_eq!(normalized, Path::new(expected), "{}", path);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn normalize_absolute_paths() {
+    let normalized = normalize_path(Path::new("//foo/./bar.txt"), true).unwrap();
+    assert_eq!(normalized, Path::new("/foo/bar.txt"));
+}
+
+#[test]
+fn reject_parent_dir_paths() {
+    for path in &["..", "foo/../bar", "/foo/.."] {
+        assert!(normalize_path(Path::new(path), false).is_err(), "{}", path);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_paths_round_trip() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let latin1_path = Path::new(OsStr::from_bytes(b"caf\xe9.txt"));
+
+    let mut entry = Entry::for_file("tests/fixtures/files/foo.txt").unwrap();
+    entry.path = latin1_path.to_owned();
+    entry.kind = Kind::Link {
+        symbolic: true,
+        target: latin1_path.to_owned(),
+    };
+
+    let encoded = protos::Entry::try_from(entry.clone()).unwrap();
+    assert!(encoded.path.is_empty());
+    assert_eq!(encoded.path_bytes, b"caf\xe9.txt");
+
+    let decoded = Entry::try_from(encoded).unwrap();
+    assert_eq!(decoded, entry);
+}
+
+#[test]
+fn detect_content_types() {
+    let path = Path::new("example.txt");
+    let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    let detected = ContentDetection::Magic.detect(path, png).unwrap();
+    assert_eq!(detected.unwrap().essence_str(), "image/png");
+
+    let detected = ContentDetection::Extension.detect(path, png).unwrap();
+    assert_eq!(detected.unwrap().essence_str(), "text/plain");
+
+    assert!(ContentDetection::Disabled
+        .detect(path, png)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn compare_tombstones() {
+    let file = Entry::for_file("tests/fixtures/files/foo.txt").unwrap();
+    let tombstone = Entry::tombstone(file.path.clone()).unwrap();
+
+    // A file which was deleted in the archive should not exist
+    assert_eq!(diff::compare(&tombstone, &file), [Difference::Present]);
+    assert_eq!(diff::compare(&file, &tombstone), [Difference::Missing]);
+    assert!(diff::compare(&tombstone, &tombstone).is_empty());
+}
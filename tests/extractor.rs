@@ -0,0 +1,153 @@
+//! Tests for extracting entries onto the local filesystem
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs, rust_2018_idioms, unused_qualifications)]
+
+use sear::{
+    entry::{
+        permissions::{SELinuxLabel, SELinuxRestore},
+        Digest, Kind,
+    },
+    error::ErrorKind,
+    Entry, Extractor,
+};
+use std::{fs, io};
+use tempfile::TempDir;
+
+/// Create an entry for an example file with the given archived path
+fn example_entry(path: &str) -> Entry {
+    let mut entry = Entry::for_file("tests/fixtures/files/foo.txt").unwrap();
+    entry.path = path.into();
+    entry
+}
+
+#[test]
+fn reject_unsafe_paths() {
+    let destination = TempDir::new().unwrap();
+    let mut extractor = Extractor::new(destination.path()).unwrap();
+
+    for path in &["/etc/passwd", "../foo.txt", "foo/../../foo.txt"] {
+        let entry = example_entry(path);
+        let contents = fs::File::open("tests/fixtures/files/foo.txt").unwrap();
+        let err = extractor.extract(&entry, contents).unwrap_err();
+        assert_eq!(*err.kind(), ErrorKind::Traversal, "{}", path);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn reject_writes_through_extracted_symlinks() {
+    let destination = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    let mut extractor = Extractor::new(destination.path()).unwrap();
+
+    let mut link = example_entry("evil");
+    link.length = 0;
+    link.kind = Kind::Link {
+        symbolic: true,
+        target: outside.path().to_owned(),
+    };
+    extractor.extract(&link, io::empty()).unwrap();
+
+    let entry = example_entry("evil/foo.txt");
+    let contents = fs::File::open("tests/fixtures/files/foo.txt").unwrap();
+    let err = extractor.extract(&entry, contents).unwrap_err();
+    assert_eq!(*err.kind(), ErrorKind::Traversal);
+    assert!(!outside.path().join("foo.txt").exists());
+
+    // Files replacing an extracted symlink must not be written through it
+    let entry = example_entry("evil");
+    let contents = fs::File::open("tests/fixtures/files/foo.txt").unwrap();
+    extractor.extract(&entry, contents).unwrap();
+    assert!(!destination.path().join("evil").is_symlink());
+    assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn reject_digest_mismatch() {
+    let destination = TempDir::new().unwrap();
+    let mut extractor = Extractor::new(destination.path()).unwrap();
+
+    let mut entry = example_entry("foo.txt");
+    entry.digest = Some(Digest::from([0u8; Digest::SIZE]));
+
+    let contents = fs::File::open("tests/fixtures/files/foo.txt").unwrap();
+    let err = extractor.extract(&entry, contents).unwrap_err();
+    assert_eq!(*err.kind(), ErrorKind::Crypto);
+
+    // Neither the file nor its unverified contents are left behind
+    assert_eq!(fs::read_dir(destination.path()).unwrap().count(), 0);
+
+    // An existing file isn't replaced by contents which fail verification
+    fs::write(destination.path().join("foo.txt"), b"original\n").unwrap();
+    let contents = fs::File::open("tests/fixtures/files/foo.txt").unwrap();
+    extractor.extract(&entry, contents).unwrap_err();
+    assert_eq!(
+        fs::read(destination.path().join("foo.txt")).unwrap(),
+        b"original\n"
+    );
+    assert_eq!(fs::read_dir(destination.path()).unwrap().count(), 1);
+}
+
+/// Labels captured when archiving are restored on extraction. This is only
+/// checked on systems with SELinux enabled, where files are labeled.
+#[test]
+fn restore_selinux_labels() {
+    let source = TempDir::new().unwrap();
+    let destination = TempDir::new().unwrap();
+    let source_path = source.path().join("foo.txt");
+    fs::copy("tests/fixtures/files/foo.txt", &source_path).unwrap();
+
+    let label = match SELinuxLabel::for_file(&source_path).unwrap() {
+        Some(label) => label,
+        None => return,
+    };
+
+    let mut entry = Entry::for_file(&source_path).unwrap();
+    assert_eq!(entry.permissions.selinux_labels.first(), Some(&label));
+    entry.path = "foo.txt".into();
+
+    let mut extractor = Extractor::new(destination.path()).unwrap();
+    extractor.selinux(SELinuxRestore::Archived);
+
+    let contents = fs::File::open(&source_path).unwrap();
+    extractor.extract(&entry, contents).unwrap();
+    extractor.finish().unwrap();
+
+    let extracted = SELinuxLabel::for_file(&destination.path().join("foo.txt")).unwrap();
+    assert_eq!(extracted, Some(label));
+}
+
+/// Default labels are applied by a single `restorecon` run over the extracted
+/// trees once extraction finishes. This is only checked on systems with
+/// SELinux enabled, where files are labeled.
+#[test]
+fn relabel_selinux_defaults() {
+    let destination = TempDir::new().unwrap();
+    let mut extractor = Extractor::new(destination.path()).unwrap();
+    extractor.selinux(SELinuxRestore::Default);
+
+    // Nothing needs relabeling (or `restorecon`) when nothing was extracted
+    extractor.finish().unwrap();
+
+    if SELinuxLabel::for_file(&destination.path())
+        .unwrap()
+        .is_none()
+    {
+        return;
+    }
+
+    let mut entry = Entry::for_file("tests/fixtures/files/foo.txt").unwrap();
+    entry.path = "dir/foo.txt".into();
+    entry.permissions.selinux_labels.clear();
+
+    let mut extractor = Extractor::new(destination.path()).unwrap();
+    extractor.selinux(SELinuxRestore::Default);
+
+    let contents = fs::File::open("tests/fixtures/files/foo.txt").unwrap();
+    extractor.extract(&entry, contents).unwrap();
+    extractor.finish().unwrap();
+
+    let extracted = destination.path().join("dir/foo.txt");
+    assert!(SELinuxLabel::for_file(&extracted).unwrap().is_some());
+}
@@ -0,0 +1,29 @@
+//! Tests for file owners
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs, rust_2018_idioms, unused_qualifications)]
+
+use sear::entry::owner::Name;
+
+#[test]
+fn parse_names() {
+    for name in &[
+        "root",
+        "www-data",
+        "svc_backup",
+        "Administrator",
+        "WS01$",
+        "_apt",
+        "a.b",
+    ] {
+        let parsed = name.parse::<Name>().unwrap();
+        assert_eq!(parsed.as_ref(), *name);
+    }
+}
+
+#[test]
+fn reject_malformed_names() {
+    for name in &["", "-root", "0day", "$", "a$b", "user name", "user:group"] {
+        assert!(name.parse::<Name>().is_err(), "{}", name);
+    }
+}
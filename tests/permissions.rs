@@ -0,0 +1,113 @@
+//! Tests for file permissions
+
+#![forbid(unsafe_code)]
+#![warn(missing_docs, rust_2018_idioms, unused_qualifications)]
+
+use sear::entry::permissions::{
+    mode::{FileType, ModeChange},
+    Mode, SELinuxLabel,
+};
+use std::convert::TryFrom;
+
+#[test]
+fn parse_selinux_labels() {
+    for label in &[
+        "system_u:object_r:httpd_sys_content_t:s0",
+        "unconfined_u:object_r:user_home_t:s0-s0:c0.c1023",
+        "system_u:object_r:container_file_t:s0:c12,c345",
+        "user_u:object_r:tmp_t",
+    ] {
+        let parsed = label.parse::<SELinuxLabel>().unwrap();
+        assert_eq!(parsed.as_ref(), *label);
+    }
+}
+
+#[test]
+fn reject_malformed_selinux_labels() {
+    for label in &[
+        "",
+        "httpd_sys_content_t",
+        "system_u:object_r",
+        "system_u::httpd_sys_content_t:s0",
+        "system_u:object_r:httpd_sys_content_t:",
+        "system_u:object_r:httpd_sys_content_t:x0",
+        "system_u:object_r:httpd_sys_content_t:s0:c1.",
+        "system_u:object_r:httpd sys:s0",
+    ] {
+        assert!(label.parse::<SELinuxLabel>().is_err(), "{}", label);
+    }
+}
+
+#[test]
+fn mode_from_u32() {
+    let mode = Mode::try_from(0o104_755).unwrap();
+    assert_eq!(mode.file_type(), Some(FileType::Regular));
+    assert_eq!(mode.permissions(), 0o4755);
+    assert!(mode.is_setuid());
+    assert!(!mode.is_setgid());
+    assert!(!mode.is_sticky());
+    assert!(mode.user().write);
+    assert!(!mode.group().write);
+    assert!(mode.other().execute);
+}
+
+#[test]
+fn reject_undefined_mode_bits() {
+    assert!(Mode::try_from(0o200_644).is_err());
+    assert!(Mode::try_from(0o070_644).is_err());
+}
+
+#[test]
+fn mode_ls_formatted() {
+    for (mode, expected) in &[
+        (0o100_755, "-rwxr-xr-x"),
+        (0o100_644, "-rw-r--r--"),
+        (0o041_777, "drwxrwxrwt"),
+        (0o120_777, "lrwxrwxrwx"),
+        (0o106_644, "-rwSr-Sr--"),
+        (0o104_711, "-rws--x--x"),
+    ] {
+        assert_eq!(Mode::try_from(*mode).unwrap().ls_formatted(), *expected);
+    }
+}
+
+#[test]
+fn parse_modes() {
+    for (s, expected) in &[
+        ("644", 0o644),
+        ("4755", 0o4755),
+        ("u=rwx,g=rx,o=", 0o750),
+        ("a=r,u+w", 0o644),
+        ("ug=rw,o=r", 0o664),
+        ("=rwx,g-w,o-wx", 0o754),
+    ] {
+        assert_eq!(u32::from(s.parse::<Mode>().unwrap()), *expected, "{}", s);
+    }
+
+    for s in &["", "8", "10000", "u", "z+r", "u+q"] {
+        assert!(s.parse::<Mode>().is_err(), "{}", s);
+    }
+}
+
+#[test]
+fn apply_mode_changes() {
+    let file = Mode::try_from(0o100_644).unwrap();
+    let dir = Mode::try_from(0o040_755).unwrap();
+
+    for (change, mode, umask, expected) in &[
+        ("go-r", file, 0o022, 0o100_600),
+        ("a+X", file, 0o022, 0o100_644),
+        ("a+X", dir, 0o022, 0o040_755),
+        ("u+s,+t", dir, 0o022, 0o045_755),
+        ("g=u", file, 0o022, 0o100_664),
+        ("600", dir, 0o022, 0o040_600),
+        ("+w", file, 0o022, 0o100_644),
+        ("+w", file, 0o002, 0o100_664),
+        ("a+w", file, 0o022, 0o100_666),
+        ("=r", file, 0o022, 0o100_444),
+        ("=w", file, 0o022, 0o100_200),
+    ] {
+        let change = change.parse::<ModeChange>().unwrap();
+        assert_eq!(u32::from(change.apply(*mode, *umask).unwrap()), *expected);
+    }
+}